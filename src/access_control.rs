@@ -23,12 +23,50 @@
 //! `admins` is the manager of all roles (excluding itself), and is the only role that can configure the role admins.
 //!
 //! A `role_admin` can add/revoke principals to that role, but cannot configure the role admins.
-//! A fixed number of 32 roles are defined, and each role is represented by a number of `u8` in [0,32).
-//! This number was chosen for the most space-efficient implementation, and should be enough for all practical applications.
+//! A fixed number of 256 roles are defined, and each role is represented by a number of `u8` in [0,256).
+//! The roles of a principal and the role admins of a role are stored as a 256-bit [`RoleSet`].
 //! Unused roles can simply be ignored.
+//!
+//! Each role can be granted until an expiry time. Expired grants are treated as absent by all checks and queries,
+//! and are removed by [`prune_expired_roles`] or [`remove_expired_roles`].
+//!
+//! Earlier versions supported 32 roles stored as `u32` bitflags.
+//! These are migrated in place by `rustic_post_upgrade`.
+//!
+//! Roles can be given a unique name and a description with [`define_role`], so that clients do not need to hard-code role numbers.
+//! The [`has_role_named`] guard checks a role by its name, e.g. `#[modifiers("has_role_named@\"minter\"")]`.
+//!
+//! A role holder can delegate its roles to a deputy for a bounded time with [`delegate_roles`],
+//! and revoke the delegation with [`revoke_delegation`]. Role checks honor unexpired delegations.
+//!
+//! A reverse index from each role to its members is maintained, so that the members of a role
+//! can be listed with [`get_role_members`] and counted with [`get_role_member_count`].
+//!
+//! Guards can be combined into boolean expressions with [`any_of`], [`all_of`] and [`not`] over [`Guard`] values,
+//! e.g. "owner or role 3", and checked with the [`guard`] guard.
+//!
+//! Changes that would lock the canister are refused: the last admin cannot be revoked or renounce,
+//! and [`transfer_ownership_immediate`] cannot clear the owner.
+//! Leaving the canister without an owner is only possible with [`renounce_ownership`] and its confirmation token.
+//!
+//! A pending ownership transfer expires after a timeout, which is [`DEFAULT_OWNERSHIP_TRANSFER_TIMEOUT`] unless
+//! changed with [`set_ownership_transfer_timeout`]. Expired transfers cannot be accepted.
+//!
+//! With the `multisig` feature, granting and revoking admins and changing role admins can be restricted
+//! to M-of-N admin approved proposals, see [`crate::multisig`].
+//!
+//! Admins are stored in a stable map, so the number of admins is only limited by the stable memory.
+//! They can be listed with [`get_admins`].
+//! Earlier versions stored the admins in the access control cell, and are migrated by `rustic_post_upgrade`.
+//!
+//! With the `audit-events` feature, all changes of the owner, admins, roles and role admins are recorded in the audit log.
 
-/// `grant_admin` may fail if memory page is full.
+#[cfg(feature = "audit-events")]
+use crate::audit::*;
+use crate::error::*;
 use crate::memory_map::*;
+#[cfg(feature = "multisig")]
+use crate::multisig::*;
 #[cfg(test)]
 use crate::testing::*;
 use crate::types::*;
@@ -39,19 +77,55 @@ use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
 use rustic_macros::modifiers;
 use std::cell::RefCell;
 
+/// Default timeout of a pending ownership transfer in nanoseconds (7 days).
+pub const DEFAULT_OWNERSHIP_TRANSFER_TIMEOUT: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Time in nanoseconds during which a token returned by [`renounce_ownership_confirmation`] can be used (10 minutes).
+pub const RENOUNCE_CONFIRMATION_TIMEOUT: u64 = 10 * 60 * 1_000_000_000;
+
+fn default_ownership_transfer_timeout() -> u64 {
+    DEFAULT_OWNERSHIP_TRANSFER_TIMEOUT
+}
+
 #[derive(Clone, CandidType, serde::Serialize, serde::Deserialize)]
 struct AccessControl {
     owner: Option<Principal>,
     pending_owner: Option<Principal>,
-    admins: Vec<Principal>,
-    // bitflag of admins for each role
-    // this is the list of all roles that manage a specific role_i (when the corresponding bitflag is set to 1).
-    // Role 0: 0 0 0 ... 1 1 0 0 <- role 0 is managed by role 2 and role 3
-    // Role 1: 0 0 0 ... 0 0 0 1 <- role 1 is managed by role 0
-    // ...
-    // Role x: r31 r30 r29 ... r2 r1 r0
-    // It's a bad idea to have a role manage itself or have circular management relationships (but this library would allow it nevertheless)
-    admins_of_role: [u32; 32],
+    // time when the pending transfer was proposed
+    #[serde(default)]
+    pending_owner_proposed_at: Option<u64>,
+    // time after which the pending transfer can no longer be accepted
+    #[serde(default)]
+    pending_owner_deadline: Option<u64>,
+    #[serde(default = "default_ownership_transfer_timeout")]
+    ownership_transfer_timeout: u64,
+    // nonce of the last renounce confirmation token, incremented for each token
+    #[serde(default)]
+    renounce_nonce: u64,
+    // time after which the last renounce confirmation token can no longer be used, `None` if there is no token
+    #[serde(default)]
+    renounce_deadline: Option<u64>,
+    // admins, used before the admins were moved to `ADMINS`. Only read during migration.
+    #[serde(rename = "admins", default)]
+    legacy_admins: Vec<Principal>,
+    // bitflag of admins for each of the first 32 roles, used before the role admins were moved to `ROLE_ADMINS`.
+    // Only read during migration.
+    #[serde(rename = "admins_of_role", default)]
+    legacy_admins_of_role: [u32; 32],
+}
+
+impl AccessControl {
+    // Sets the owner, which invalidates any renounce confirmation token.
+    fn set_owner(&mut self, owner: Option<Principal>) {
+        self.owner = owner;
+        self.renounce_deadline = None;
+    }
+
+    fn clear_pending_owner(&mut self) {
+        self.pending_owner = None;
+        self.pending_owner_proposed_at = None;
+        self.pending_owner_deadline = None;
+    }
 }
 
 thread_local! {
@@ -62,11 +136,22 @@ thread_local! {
             Cbor(Some(AccessControl {
                 owner: Some(canister_caller()),
                 pending_owner: None,
-                admins: vec![canister_caller()],
-                admins_of_role: Default::default(),
+                pending_owner_proposed_at: None,
+                pending_owner_deadline: None,
+                ownership_transfer_timeout: DEFAULT_OWNERSHIP_TRANSFER_TIMEOUT,
+                renounce_nonce: 0,
+                renounce_deadline: None,
+                legacy_admins: vec![],
+                legacy_admins_of_role: Default::default(),
             })),
         ).expect("Failed to initialize the access control cell")
     );
+    // can be lazily initialized
+    static ADMINS: RefCell<StableBTreeMap<StablePrincipal, (), VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(ADMINS_MEM_ID)))
+    });
 }
 
 pub(crate) fn access_init(owner: Principal) {
@@ -74,12 +159,36 @@ pub(crate) fn access_init(owner: Principal) {
         let mut c = c.borrow_mut();
         #[allow(clippy::unwrap_used)] // unwrap desired
         let mut config = c.get().0.clone().unwrap();
-        config.owner = Some(owner);
-        config.admins = vec![owner];
+        config.set_owner(Some(owner));
         #[allow(clippy::expect_used)] // unwrap desired
         c.set(Cbor(Some(config)))
             .expect("Access control init failed");
     });
+    ADMINS.with(|a| {
+        let mut a = a.borrow_mut();
+        a.clear_new();
+        a.insert(owner.into(), ());
+    });
+}
+
+// Moves the admins from the access control cell of earlier versions to `ADMINS`.
+pub(crate) fn admins_migrate() {
+    ACCESS_CONTROL.with(|c| {
+        let mut c = c.borrow_mut();
+        #[allow(clippy::unwrap_used)] // unwrap desired
+        let mut config = c.get().0.clone().unwrap();
+        if config.legacy_admins.is_empty() {
+            return;
+        }
+        ADMINS.with(|a| {
+            let mut a = a.borrow_mut();
+            for admin in config.legacy_admins.drain(..) {
+                a.insert(admin.into(), ());
+            }
+        });
+        #[allow(clippy::expect_used)] // unwrap desired
+        c.set(Cbor(Some(config))).expect("Admin migration failed");
+    });
 }
 
 /// Checks if the caller is the owner.
@@ -94,12 +203,15 @@ pub(crate) fn access_init(owner: Principal) {
 /// fn my_func() {}
 /// ```
 pub fn only_owner() -> Result<(), String> {
-    let caller = canister_caller();
-    #[allow(clippy::unwrap_used)] // unwrap desired
-    if ACCESS_CONTROL.with(|c| c.borrow().get().0.clone().unwrap().owner) == Some(caller) {
+    Ok(check_owner()?)
+}
+
+/// Checks if the caller is the owner, returning a typed error.
+pub fn check_owner() -> Result<(), RusticError> {
+    if is_owner(canister_caller()) {
         Ok(())
     } else {
-        Err("Caller is not the owner".to_string())
+        Err(RusticError::NotOwner)
     }
 }
 
@@ -112,32 +224,52 @@ pub fn is_owner(owner: Principal) -> bool {
     }) == Some(owner)
 }
 
+fn check_principal_not_anonymous(principal: &Option<Principal>) -> Result<(), RusticError> {
+    if *principal == Some(Principal::anonymous()) {
+        Err(RusticError::AnonymousPrincipal)
+    } else {
+        Ok(())
+    }
+}
+
 /// Transfers ownership to a new Principal in a 2-step transfer process.
 /// Must be called by the current `owner`
 ///
 /// First, the original owner calls this function, specifying the new owner.
 /// The ownership is not affected until the new owners calls [`accept_ownership`],
 /// at which point ownership would be transfered from the original owner to the new owner.
+/// The new owner must accept before the ownership transfer timeout has passed.
 /// If the `new_owner` is set to `None`, then any pending ownership transfer is cancelled.
 #[update]
 #[modifiers("only_owner")]
 pub fn transfer_ownership(new_owner: Option<Principal>) {
-    if let Some(x) = new_owner {
-        assert_ne!(
-            x,
-            Principal::anonymous(),
-            "Cannot transfer ownership to the anonymous principal"
-        );
-    }
+    unwrap_or_reject(try_transfer_ownership(new_owner))
+}
+
+/// Same as [`transfer_ownership`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_transfer_ownership(new_owner: Option<Principal>) -> Result<(), RusticError> {
+    check_owner()?;
+    check_principal_not_anonymous(&new_owner)?;
     ACCESS_CONTROL.with(|c| {
         let mut c = c.borrow_mut();
         #[allow(clippy::unwrap_used)] // unwrap desired
         let mut config = c.get().0.clone().unwrap();
+        let now = canister_time();
         config.pending_owner = new_owner;
+        config.pending_owner_proposed_at = new_owner.map(|_| now);
+        config.pending_owner_deadline =
+            new_owner.map(|_| now.saturating_add(config.ownership_transfer_timeout));
         #[allow(clippy::expect_used)] // unwrap desired
         c.set(Cbor(Some(config)))
             .expect("Ownership transfer failed");
     });
+    #[cfg(feature = "audit-events")]
+    match new_owner {
+        Some(_) => emit(AuditAction::OwnershipTransferStarted, new_owner),
+        None => emit(AuditAction::OwnershipTransferCancelled, None),
+    }
+    Ok(())
 }
 
 /// Transfers ownership to a new Principal in a single-step transfer process.
@@ -145,64 +277,148 @@ pub fn transfer_ownership(new_owner: Option<Principal>) {
 ///
 /// The use of this function is discouraged, as there is no recourse if the wrong principal is specified.
 /// This function is useful in cases where the accepting principal cannot call [`accept_ownership`].
+/// The `new_owner` must not be `None`, use [`renounce_ownership`] to leave the canister without an owner.
 #[update]
 #[modifiers("only_owner")]
 pub fn transfer_ownership_immediate(new_owner: Option<Principal>) {
-    if let Some(x) = new_owner {
-        assert_ne!(
-            x,
-            Principal::anonymous(),
-            "Cannot transfer ownership to the anonymous principal"
-        );
+    unwrap_or_reject(try_transfer_ownership_immediate(new_owner))
+}
+
+/// Same as [`transfer_ownership_immediate`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_transfer_ownership_immediate(new_owner: Option<Principal>) -> Result<(), RusticError> {
+    check_owner()?;
+    check_principal_not_anonymous(&new_owner)?;
+    if new_owner.is_none() {
+        return Err(RusticError::NoOwner);
     }
     ACCESS_CONTROL.with(|c| {
         let mut c = c.borrow_mut();
         #[allow(clippy::unwrap_used)] // unwrap desired
         let mut config = c.get().0.clone().unwrap();
-        config.pending_owner = None;
-        config.owner = new_owner;
+        config.clear_pending_owner();
+        config.set_owner(new_owner);
         #[allow(clippy::expect_used)] // unwrap desired
         c.set(Cbor(Some(config)))
             .expect("Ownership transfer failed");
     });
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::OwnershipTransferred, new_owner);
+    Ok(())
+}
+
+// The renounce confirmation token of an owner. It names the canister and the owner,
+// so that it cannot be reused for another canister or after a change of ownership.
+fn renounce_token(owner: Principal, nonce: u64) -> String {
+    format!(
+        "renounce ownership of {} by {} #{}",
+        canister_id(),
+        owner,
+        nonce
+    )
+}
+
+/// Issues the confirmation token that must be passed to [`renounce_ownership`]. Must be called by the current `owner`.
+/// The token can be used once within [`RENOUNCE_CONFIRMATION_TIMEOUT`], and issuing a new token invalidates the previous one.
+#[update]
+#[modifiers("only_owner")]
+pub fn renounce_ownership_confirmation() -> String {
+    unwrap_or_reject(try_renounce_ownership_confirmation())
+}
+
+/// Same as [`renounce_ownership_confirmation`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_renounce_ownership_confirmation() -> Result<String, RusticError> {
+    check_owner()?;
+    let token = ACCESS_CONTROL.with(|c| {
+        let mut c = c.borrow_mut();
+        #[allow(clippy::unwrap_used)] // unwrap desired
+        let mut config = c.get().0.clone().unwrap();
+        config.renounce_nonce += 1;
+        config.renounce_deadline =
+            Some(canister_time().saturating_add(RENOUNCE_CONFIRMATION_TIMEOUT));
+        let token = renounce_token(canister_caller(), config.renounce_nonce);
+        #[allow(clippy::expect_used)] // unwrap desired
+        c.set(Cbor(Some(config)))
+            .expect("Renounce confirmation failed");
+        token
+    });
+    Ok(token)
 }
 
-/// Renounces ownership. Must be called by the current `owner`.
+/// Renounces ownership, leaving the canister without an owner. Must be called by the current `owner`.
+///
+/// This cannot be undone, so the `confirmation` must be the latest unexpired token
+/// issued by [`renounce_ownership_confirmation`].
+/// The admins are not affected.
 #[update]
 #[modifiers("only_owner")]
-pub fn renounce_ownership() {
+pub fn renounce_ownership(confirmation: String) {
+    unwrap_or_reject(try_renounce_ownership(confirmation))
+}
+
+/// Same as [`renounce_ownership`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_renounce_ownership(confirmation: String) -> Result<(), RusticError> {
+    check_owner()?;
     ACCESS_CONTROL.with(|c| {
         let mut c = c.borrow_mut();
         #[allow(clippy::unwrap_used)] // unwrap desired
         let mut config = c.get().0.clone().unwrap();
-        config.owner = None;
-        config.pending_owner = None;
+        let deadline = config
+            .renounce_deadline
+            .ok_or(RusticError::InvalidConfirmation)?;
+        if confirmation != renounce_token(canister_caller(), config.renounce_nonce) {
+            return Err(RusticError::InvalidConfirmation);
+        }
+        if canister_time() > deadline {
+            return Err(RusticError::ConfirmationExpired);
+        }
+        config.set_owner(None);
+        config.clear_pending_owner();
         #[allow(clippy::expect_used)] // unwrap desired
         c.set(Cbor(Some(config)))
             .expect("Ownership transfer failed");
-    });
+        Ok(())
+    })?;
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::OwnershipRenounced, None);
+    Ok(())
 }
 
 /// Accepts ownership transfer. The caller must be the pending owner.
 #[update]
 pub fn accept_ownership() {
+    unwrap_or_reject(try_accept_ownership())
+}
+
+/// Same as [`accept_ownership`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_accept_ownership() -> Result<(), RusticError> {
     ACCESS_CONTROL.with(|c| {
         let mut c = c.borrow_mut();
         #[allow(clippy::unwrap_used)] // unwrap desired
         let mut config = c.get().0.clone().unwrap();
-        #[allow(clippy::expect_used)] // unwrap desired
-        let new_owner = config.pending_owner.expect("No pending owner");
-        assert_eq!(
-            new_owner,
-            canister_caller(),
-            "Only pending owner can accept ownership"
-        );
-        config.owner = Some(new_owner);
-        config.pending_owner = None;
+        let new_owner = config.pending_owner.ok_or(RusticError::NoPendingOwner)?;
+        if new_owner != canister_caller() {
+            return Err(RusticError::NotPendingOwner);
+        }
+        if config
+            .pending_owner_deadline
+            .map_or(false, |d| canister_time() > d)
+        {
+            return Err(RusticError::OwnershipTransferExpired);
+        }
+        config.set_owner(Some(new_owner));
+        config.clear_pending_owner();
         #[allow(clippy::expect_used)] // unwrap desired
         c.set(Cbor(Some(config)))
             .expect("Ownership transfer failed");
-    });
+        Ok(())
+    })?;
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::OwnershipTransferred, Some(canister_caller()));
+    Ok(())
 }
 
 /// Query method to get the current owner.
@@ -219,12 +435,71 @@ pub fn pending_owner() -> Option<Principal> {
     ACCESS_CONTROL.with(|c| c.borrow().get().0.clone().unwrap().pending_owner)
 }
 
-/// Query method to get the current owner and pending owner.
+/// Query method to get the current owner, pending owner, and the deadline of the pending transfer.
+/// The deadline is in nanoseconds since the epoch.
 #[query]
-pub fn owner_and_pending_owner() -> (Option<Principal>, Option<Principal>) {
+pub fn owner_and_pending_owner() -> (Option<Principal>, Option<Principal>, Option<u64>) {
     #[allow(clippy::unwrap_used)] // unwrap desired
     let config = ACCESS_CONTROL.with(|c| c.borrow().get().0.clone().unwrap());
-    (config.owner, config.pending_owner)
+    (
+        config.owner,
+        config.pending_owner,
+        config.pending_owner_deadline,
+    )
+}
+
+/// Query method to get the time when the pending ownership transfer was proposed.
+#[query]
+pub fn pending_owner_proposed_at() -> Option<u64> {
+    #[allow(clippy::unwrap_used)] // unwrap desired
+    ACCESS_CONTROL.with(|c| {
+        c.borrow()
+            .get()
+            .0
+            .clone()
+            .unwrap()
+            .pending_owner_proposed_at
+    })
+}
+
+/// Query method to get the timeout of ownership transfers in nanoseconds.
+#[query]
+pub fn ownership_transfer_timeout() -> u64 {
+    #[allow(clippy::unwrap_used)] // unwrap desired
+    ACCESS_CONTROL.with(|c| {
+        c.borrow()
+            .get()
+            .0
+            .clone()
+            .unwrap()
+            .ownership_transfer_timeout
+    })
+}
+
+/// Sets the timeout of ownership transfers in nanoseconds. Must be called by the current `owner`.
+/// Only applies to transfers started after this call.
+#[update]
+#[modifiers("only_owner")]
+pub fn set_ownership_transfer_timeout(timeout: u64) {
+    unwrap_or_reject(try_set_ownership_transfer_timeout(timeout))
+}
+
+/// Same as [`set_ownership_transfer_timeout`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_set_ownership_transfer_timeout(timeout: u64) -> Result<(), RusticError> {
+    check_owner()?;
+    ACCESS_CONTROL.with(|c| {
+        let mut c = c.borrow_mut();
+        #[allow(clippy::unwrap_used)] // unwrap desired
+        let mut config = c.get().0.clone().unwrap();
+        config.ownership_transfer_timeout = timeout;
+        #[allow(clippy::expect_used)] // unwrap desired
+        c.set(Cbor(Some(config)))
+            .expect("Set ownership transfer timeout failed");
+    });
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::OwnershipTransferTimeoutSet(timeout), None);
+    Ok(())
 }
 
 /// Checks if the caller is the admin.
@@ -239,226 +514,933 @@ pub fn owner_and_pending_owner() -> (Option<Principal>, Option<Principal>) {
 /// fn my_func() {}
 /// ```
 pub fn only_admin() -> Result<(), String> {
-    let caller = canister_caller();
-    #[allow(clippy::unwrap_used)] // unwrap desired
-    if ACCESS_CONTROL.with(|c| c.borrow().get().0.clone().unwrap().admins.contains(&caller)) {
+    Ok(check_admin()?)
+}
+
+/// Checks if the caller is the admin, returning a typed error.
+pub fn check_admin() -> Result<(), RusticError> {
+    if is_admin(canister_caller()) {
         Ok(())
     } else {
-        Err("Caller is not an admin".to_string())
+        Err(RusticError::NotAdmin)
     }
 }
 
 /// Checks if a principal is an admin.
 #[query]
 pub fn is_admin(admin: Principal) -> bool {
-    #[allow(clippy::unwrap_used)] // unwrap desired
-    ACCESS_CONTROL.with(|c| c.borrow().get().0.clone().unwrap().admins.contains(&admin))
+    ADMINS.with(|a| a.borrow().contains_key(&admin.into()))
 }
 
 /// Grants admin to a new Principal. Must be called by the `owner`.
 #[update]
 #[modifiers("only_owner")]
+#[cfg_attr(feature = "multisig", modifiers("when_multisig_disabled"))]
 pub fn grant_admin(new_admin: Principal) {
-    assert_ne!(
-        new_admin,
-        Principal::anonymous(),
-        "Cannot grant admin to the anonymous principal"
-    );
-    ACCESS_CONTROL.with(|c| {
-        let mut c = c.borrow_mut();
-        #[allow(clippy::unwrap_used)] // unwrap desired
-        let mut config = c.get().0.clone().unwrap();
-        if !config.admins.contains(&new_admin) {
-            config.admins.push(new_admin);
-            #[allow(clippy::expect_used)] // unwrap desired
-            c.set(Cbor(Some(config))).expect("Grant admin failed");
-        }
-    });
+    unwrap_or_reject(try_grant_admin(new_admin))
+}
+
+/// Same as [`grant_admin`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_grant_admin(new_admin: Principal) -> Result<(), RusticError> {
+    check_owner()?;
+    #[cfg(feature = "multisig")]
+    check_multisig_disabled()?;
+    check_admin_grantee(new_admin)?;
+    add_admin(new_admin);
+    Ok(())
+}
+
+// Checks that a principal can be granted admin.
+pub(crate) fn check_admin_grantee(new_admin: Principal) -> Result<(), RusticError> {
+    check_principal_not_anonymous(&Some(new_admin))
+}
+
+// Grants admin without checks, see `check_admin_grantee`.
+pub(crate) fn add_admin(new_admin: Principal) {
+    ADMINS.with(|a| a.borrow_mut().insert(new_admin.into(), ()));
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::AdminGranted, Some(new_admin));
 }
 
 /// Revokes admin from a Principal. Must be called by the `owner`.
+/// The last admin cannot be revoked.
 #[update]
 #[modifiers("only_owner")]
+#[cfg_attr(feature = "multisig", modifiers("when_multisig_disabled"))]
 pub fn revoke_admin(admin: Principal) {
-    ACCESS_CONTROL.with(|c| {
-        let mut c = c.borrow_mut();
-        #[allow(clippy::unwrap_used)] // unwrap desired
-        let mut config = c.get().0.clone().unwrap();
-        config.admins.retain(|x| x != &admin);
-        #[allow(clippy::expect_used)] // unwrap desired
-        c.set(Cbor(Some(config))).expect("Revoke admin failed");
-    });
+    unwrap_or_reject(try_revoke_admin(admin))
+}
+
+/// Same as [`revoke_admin`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_revoke_admin(admin: Principal) -> Result<(), RusticError> {
+    check_owner()?;
+    #[cfg(feature = "multisig")]
+    check_multisig_disabled()?;
+    check_admin_removal(admin)?;
+    remove_admin(admin);
+    Ok(())
+}
+
+// Checks that revoking admin from a principal leaves enough admins to manage the canister:
+// at least one, and at least the multisig threshold with the `multisig` feature.
+pub(crate) fn check_admin_removal(admin: Principal) -> Result<(), RusticError> {
+    if !is_admin(admin) {
+        return Ok(());
+    }
+    let remaining = get_admin_count() - 1;
+    if remaining == 0 {
+        return Err(RusticError::LastAdmin);
+    }
+    #[cfg(feature = "multisig")]
+    if remaining < get_multisig_config().threshold as u64 {
+        return Err(RusticError::ThresholdTooHigh);
+    }
+    Ok(())
+}
+
+// Revokes admin without checks, see `check_admin_removal`.
+pub(crate) fn remove_admin(admin: Principal) {
+    ADMINS.with(|a| a.borrow_mut().remove(&admin.into()));
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::AdminRevoked, Some(admin));
+}
+
+/// Maximum number of admins returned in a single page.
+pub const MAX_ADMINS_PAGE_SIZE: u64 = 1000;
+
+/// A page of admins.
+/// `next` is the principal to pass as `start` for the following page, or `None` if there are no more admins.
+#[derive(Clone, CandidType, serde::Serialize, serde::Deserialize)]
+pub struct AdminsPage {
+    pub admins: Vec<Principal>,
+    pub next: Option<Principal>,
+}
+
+/// Returns a page of at most `limit` admins, in ascending order starting from `start`.
+#[query]
+pub fn get_admins(start: Option<Principal>, limit: u64) -> AdminsPage {
+    let start = start.unwrap_or(Principal::management_canister());
+    let limit = limit.min(MAX_ADMINS_PAGE_SIZE) as usize;
+    ADMINS.with(|a| {
+        let mut admins: Vec<Principal> = a
+            .borrow()
+            .range(StablePrincipal::from(start)..)
+            .take(limit + 1)
+            .map(|(p, _)| (&p).into())
+            .collect();
+        let next = (admins.len() > limit).then(|| admins.pop()).flatten();
+        AdminsPage { admins, next }
+    })
+}
+
+/// Returns the number of admins.
+#[query]
+pub fn get_admin_count() -> u64 {
+    ADMINS.with(|a| a.borrow().len())
 }
 
 /// Revokes admin from the caller. Must be called by the admin itself.
+/// The last admin cannot renounce.
 #[update]
 #[modifiers("only_admin")]
 pub fn renounce_admin() {
+    unwrap_or_reject(try_renounce_admin())
+}
+
+/// Same as [`renounce_admin`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_renounce_admin() -> Result<(), RusticError> {
+    check_admin()?;
     let admin = canister_caller();
-    ACCESS_CONTROL.with(|c| {
-        let mut c = c.borrow_mut();
-        #[allow(clippy::unwrap_used)] // unwrap desired
-        let mut config = c.get().0.clone().unwrap();
-        config.admins.retain(|x| x != &admin);
-        #[allow(clippy::expect_used)] // unwrap desired
-        c.set(Cbor(Some(config))).expect("Revoke admin failed");
-    });
+    check_admin_removal(admin)?;
+    ADMINS.with(|a| a.borrow_mut().remove(&admin.into()));
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::AdminRenounced, Some(admin));
+    Ok(())
 }
 
 // `access-roles` feature
 
+/// A role to grant, with an optional expiry time in nanoseconds since the epoch.
+#[cfg(feature = "access-roles")]
+pub type RoleGrant = (u8, Option<u64>);
+
+// A role grant expiring at a time, used as the key of the expiry queue.
+#[cfg(feature = "access-roles")]
+type ExpiryKey = (u64, (StablePrincipal, u8));
+
+// A pair of principals used as the key of the delegation maps.
+#[cfg(feature = "access-roles")]
+type PrincipalPair = (StablePrincipal, StablePrincipal);
+
 thread_local! {
     // can be lazily initialized
-    // mapping from Principal to bitflag of roles
-    static ACCESS_ROLES: RefCell<StableBTreeMap<StablePrincipal, u32, VM>> =
+    // mapping from Principal to the set of roles
+    #[cfg(feature = "access-roles")]
+    static ACCESS_ROLES: RefCell<StableBTreeMap<StablePrincipal, RoleSet, VM>> =
         MEMORY_MANAGER.with(|mm| {
             RefCell::new(StableBTreeMap::init(
                 mm.borrow().get(ACCESS_ROLES_MEM_ID)))
     });
+    // mapping from Principal to bitflag of the first 32 roles, used by earlier versions
+    // only read during migration
+    #[cfg(feature = "access-roles")]
+    static LEGACY_ACCESS_ROLES: RefCell<StableBTreeMap<StablePrincipal, u32, VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(LEGACY_ACCESS_ROLES_MEM_ID)))
+    });
+    // expiry time of time-bound role grants
+    #[cfg(feature = "access-roles")]
+    static ROLE_EXPIRIES: RefCell<StableBTreeMap<(StablePrincipal, u8), u64, VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(ROLE_EXPIRIES_MEM_ID)))
+    });
+    // the time-bound role grants ordered by expiry, so that expired grants are found without a scan
+    #[cfg(feature = "access-roles")]
+    static ROLE_EXPIRY_QUEUE: RefCell<StableBTreeMap<ExpiryKey, (), VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(ROLE_EXPIRY_QUEUE_MEM_ID)))
+    });
+    // mapping from role to the set of roles that manage it
+    // It's a bad idea to have a role manage itself or have circular management relationships (but this library would allow it nevertheless)
+    #[cfg(feature = "access-roles")]
+    static ROLE_ADMINS: RefCell<StableBTreeMap<u8, RoleSet, VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(ROLE_ADMINS_MEM_ID)))
+    });
+    // reverse index from role to its members
+    #[cfg(feature = "access-roles")]
+    static ROLE_MEMBERS: RefCell<StableBTreeMap<(u8, StablePrincipal), (), VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(ROLE_MEMBERS_MEM_ID)))
+    });
+    // registry of role names and descriptions
+    #[cfg(feature = "access-roles")]
+    static ROLE_REGISTRY: RefCell<StableBTreeMap<u8, Cbor<RoleInfo>, VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(ROLE_REGISTRY_MEM_ID)))
+    });
+    // number of members of each role
+    #[cfg(feature = "access-roles")]
+    static ROLE_MEMBER_COUNTS: RefCell<StableBTreeMap<u8, u64, VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(ROLE_MEMBER_COUNTS_MEM_ID)))
+    });
+    // mapping from (delegate, delegator) to the delegated roles and the expiry of the delegation
+    #[cfg(feature = "access-roles")]
+    static ROLE_DELEGATIONS: RefCell<StableBTreeMap<PrincipalPair, (RoleSet, u64), VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(ROLE_DELEGATIONS_MEM_ID)))
+    });
+    // reverse index of `ROLE_DELEGATIONS` from (delegator, delegate)
+    #[cfg(feature = "access-roles")]
+    static ROLE_DELEGATORS: RefCell<StableBTreeMap<PrincipalPair, (), VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(ROLE_DELEGATORS_MEM_ID)))
+    });
 }
 
-// If any role in the role flag is a role admin of another role.
-// Panics if role index is out of range.
+/// Name and description of a role.
 #[cfg(feature = "access-roles")]
-fn is_role_admin(role_flag: u32, role: u8) -> bool {
-    assert!(role <= 31, "Role must be between 0 and 31");
-    ACCESS_CONTROL.with(|c| {
-        let c = c.borrow();
-        #[allow(clippy::unwrap_used)] // unwrap desired
-        let config = c.get().0.clone().unwrap();
-        config.admins_of_role[role as usize] & role_flag != 0
-    })
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, serde::Serialize, serde::Deserialize)]
+pub struct RoleInfo {
+    pub name: String,
+    pub description: String,
 }
 
-/// Grants roles to a principal. Must be called by the `owner` or a `role_admin`.
-/// Returns a vector of booleans indicating whether each role was successfully granted, in the same order as the input.
-///
-/// When a role has already been granted prior to calling this function,
-/// but the current caller has the permission to grant the role,
-/// the return value for that role is `true`.
-/// When a role has already been granted prior to calling this function,
-/// but the current caller does not have the permission to grant the role,
-/// the return value for that role is `false`.
+/// Maximum number of members returned in a single page.
 #[cfg(feature = "access-roles")]
-#[update]
-pub fn grant_roles(roles: Vec<u8>, principal: Principal) -> Vec<bool> {
-    // caller authentication in arithmetics
-    let mut success = Vec::with_capacity(roles.len());
-    ACCESS_ROLES.with(|ar| {
-        let mut ar = ar.borrow_mut();
-        let mut principal_roles = ar.get(&principal.into()).unwrap_or(0);
-        let caller_roles = ar.get(&canister_caller().into()).unwrap_or(0);
-
-        for role in roles {
-            if role <= 31 && (is_admin(canister_caller()) || is_role_admin(caller_roles, role)) {
-                principal_roles |= 1 << role;
-                success.push(true);
-            } else {
-                success.push(false);
+pub const MAX_ROLE_MEMBERS_PAGE_SIZE: u64 = 1000;
+
+/// A page of role members.
+/// `next` is the principal to pass as `start` for the following page, or `None` if there are no more members.
+#[cfg(feature = "access-roles")]
+#[derive(Clone, CandidType, serde::Serialize, serde::Deserialize)]
+pub struct RoleMembersPage {
+    pub members: Vec<Principal>,
+    pub next: Option<Principal>,
+}
+
+// Updates the reverse index after the roles of a principal changed from `old` to `new`.
+#[cfg(feature = "access-roles")]
+fn update_role_members(principal: Principal, old: RoleSet, new: RoleSet) {
+    ROLE_MEMBERS.with(|m| {
+        let mut m = m.borrow_mut();
+        ROLE_MEMBER_COUNTS.with(|c| {
+            let mut c = c.borrow_mut();
+            for role in new.difference(&old).roles() {
+                m.insert((role, principal.into()), ());
+                let count = c.get(&role).unwrap_or(0);
+                c.insert(role, count + 1);
             }
-        }
-        #[allow(clippy::expect_used)] // unwrap desired
-        ar.insert(principal.into(), principal_roles);
+            for role in old.difference(&new).roles() {
+                m.remove(&(role, principal.into()));
+                let count = c.get(&role).unwrap_or(0);
+                c.insert(role, count.saturating_sub(1));
+            }
+        });
     });
-    success
 }
 
-/// Revokes roles from a principal. Must be called by the `owner` or a `role_admin`.
-/// Returns a vector of booleans indicating whether each role was successfully revoked, in the same order as the input.
-///
-/// When a role has already been revoked prior to calling this function,
-/// but the current caller has the permission to revoke the role,
-/// the return value for that role is `true`.
-/// When a role has already been revoked prior to calling this function,
-/// but the current caller does not have the permission to revoke the role,
-/// the return value for that role is `false`.
+// Rebuilds the reverse index from the granted roles if it is empty,
+// e.g. after upgrading from a version without the reverse index.
 #[cfg(feature = "access-roles")]
-#[update]
-pub fn revoke_roles(roles: Vec<u8>, principal: Principal) -> Vec<bool> {
-    // caller authentication arithmetics
-    let mut success = Vec::with_capacity(roles.len());
-    ACCESS_ROLES.with(|c| {
-        let mut c = c.borrow_mut();
-        let mut principal_roles = c.get(&principal.into()).unwrap_or(0);
-        let caller_roles = c.get(&canister_caller().into()).unwrap_or(0);
-
-        for role in roles {
-            if role <= 31 && (is_admin(canister_caller()) || is_role_admin(caller_roles, role)) {
-                principal_roles &= !(1 << role);
-                success.push(true);
-            } else {
-                success.push(false);
-            }
-        }
-        #[allow(clippy::expect_used)] // unwrap desired
-        c.insert(principal.into(), principal_roles)
-            .expect("Role update failed");
+pub(crate) fn role_members_init() {
+    if ROLE_MEMBERS.with(|m| !m.borrow().is_empty()) {
+        return;
+    }
+    let entries: Vec<(Principal, RoleSet)> = ACCESS_ROLES.with(|ar| {
+        ar.borrow()
+            .iter()
+            .map(|(p, roles)| ((&p).into(), roles))
+            .collect()
     });
-    success
+    for (principal, roles) in entries {
+        update_role_members(principal, RoleSet::default(), roles);
+    }
 }
 
-/// Returns the bitflag of roles granted to a principal.
+// Rebuilds the expiry queue from the role expiries if it is empty,
+// e.g. after upgrading from a version without the expiry queue.
 #[cfg(feature = "access-roles")]
-#[query]
-pub fn get_user_roles(principal: Principal) -> u32 {
-    ACCESS_ROLES.with(|c| {
-        let c = c.borrow();
-        c.get(&principal.into()).unwrap_or(0)
-    })
+pub(crate) fn role_expiry_queue_init() {
+    if ROLE_EXPIRY_QUEUE.with(|q| !q.borrow().is_empty()) {
+        return;
+    }
+    ROLE_EXPIRIES.with(|e| {
+        ROLE_EXPIRY_QUEUE.with(|q| {
+            let mut q = q.borrow_mut();
+            for ((principal, role), expires_at) in e.borrow().iter() {
+                q.insert((expires_at, (principal, role)), ());
+            }
+        })
+    });
 }
 
-/// Checks whether a principal has a certain role.
-/// Returns a boolean indicating whether the principal has the role.
+// Migrates the `u32` role bitflags of earlier versions to role sets.
+// Runs in the post-upgrade hook, and does nothing once migrated.
 #[cfg(feature = "access-roles")]
-#[query]
-pub fn user_has_role(role: u8, principal: Principal) -> bool {
-    ACCESS_ROLES.with(|c| {
-        let c = c.borrow();
-        let principal_roles = c.get(&principal.into()).unwrap_or(0);
-        principal_roles & (1 << role) != 0
+pub(crate) fn access_roles_migrate() {
+    let legacy: Vec<(StablePrincipal, u32)> =
+        LEGACY_ACCESS_ROLES.with(|ar| ar.borrow().iter().collect());
+    ACCESS_ROLES.with(|ar| {
+        let mut ar = ar.borrow_mut();
+        for (principal, flags) in legacy {
+            ar.insert(principal, RoleSet::from(flags));
+        }
+    });
+    LEGACY_ACCESS_ROLES.with(|ar| ar.borrow_mut().clear_new());
+
+    ACCESS_CONTROL.with(|c| {
+        let mut c = c.borrow_mut();
+        #[allow(clippy::unwrap_used)] // unwrap desired
+        let mut config = c.get().0.clone().unwrap();
+        if config.legacy_admins_of_role == [0; 32] {
+            return;
+        }
+        ROLE_ADMINS.with(|ra| {
+            let mut ra = ra.borrow_mut();
+            for (role, flags) in (0..).zip(config.legacy_admins_of_role) {
+                if flags != 0 {
+                    ra.insert(role, RoleSet::from(flags));
+                }
+            }
+        });
+        config.legacy_admins_of_role = [0; 32];
+        #[allow(clippy::expect_used)] // unwrap desired
+        c.set(Cbor(Some(config)))
+            .expect("Role admin migration failed");
+    });
+}
+
+// Returns the roles granted to a principal, including expired grants.
+#[cfg(feature = "access-roles")]
+fn stored_roles(principal: &Principal) -> RoleSet {
+    ACCESS_ROLES.with(|c| c.borrow().get(&principal.into()).unwrap_or_default())
+}
+
+// Returns the roles granted to a principal that have expired at time `now`.
+#[cfg(feature = "access-roles")]
+fn expired_roles(principal: &Principal, now: u64) -> RoleSet {
+    let principal: StablePrincipal = principal.into();
+    ROLE_EXPIRIES.with(|e| {
+        e.borrow()
+            .range((principal, 0)..)
+            .take_while(|((p, _), _)| *p == principal)
+            .filter(|(_, expires_at)| *expires_at <= now)
+            .map(|((_, role), _)| role)
+            .collect()
     })
 }
 
-/// Checks whether the caller has a certain role.
-/// This is typically used in conjunction with the [`modifiers`] macro.
+// Returns the roles granted to a principal, excluding expired grants.
 #[cfg(feature = "access-roles")]
-pub fn has_role(role: u8) -> Result<(), String> {
-    ACCESS_ROLES.with(|c| {
-        let c = c.borrow();
-        let principal_roles = c.get(&canister_caller().into()).unwrap_or(0);
-        if principal_roles & (1 << role) != 0 {
-            Ok(())
+fn roles_of(principal: &Principal) -> RoleSet {
+    stored_roles(principal).difference(&expired_roles(principal, canister_time()))
+}
+
+// Returns the roles of a principal including unexpired delegations.
+// Delegated roles only count while the delegator still has them.
+#[cfg(feature = "access-roles")]
+fn effective_roles(principal: &Principal) -> RoleSet {
+    let now = canister_time();
+    let delegate: StablePrincipal = principal.into();
+    let delegated = ROLE_DELEGATIONS.with(|d| {
+        d.borrow()
+            .range(
+                (
+                    delegate,
+                    StablePrincipal::from(Principal::management_canister()),
+                )..,
+            )
+            .take_while(|((p, _), _)| *p == delegate)
+            .filter(|(_, (_, expires_at))| now < *expires_at)
+            .fold(RoleSet::default(), |acc, ((_, delegator), (roles, _))| {
+                let delegator: Principal = (&delegator).into();
+                acc.union(&roles.intersection(&roles_of(&delegator)))
+            })
+    });
+    roles_of(principal).union(&delegated)
+}
+
+// Sets or clears the expiry of a role grant.
+#[cfg(feature = "access-roles")]
+fn set_role_expiry(principal: &Principal, role: u8, expires_at: Option<u64>) {
+    let key = (principal.into(), role);
+    let previous = ROLE_EXPIRIES.with(|e| {
+        let mut e = e.borrow_mut();
+        match expires_at {
+            Some(t) => e.insert(key, t),
+            None => e.remove(&key),
+        }
+    });
+    ROLE_EXPIRY_QUEUE.with(|q| {
+        let mut q = q.borrow_mut();
+        if let Some(t) = previous {
+            q.remove(&(t, key));
+        }
+        if let Some(t) = expires_at {
+            q.insert((t, key), ());
+        }
+    });
+}
+
+// Returns whether the grant of a role to a principal has expired at time `now`.
+#[cfg(feature = "access-roles")]
+fn is_grant_expired(principal: StablePrincipal, role: u8, now: u64) -> bool {
+    ROLE_EXPIRIES.with(|e| e.borrow().get(&(principal, role)).is_some_and(|t| t <= now))
+}
+
+// If any role in the role set is a role admin of another role.
+#[cfg(feature = "access-roles")]
+fn is_role_admin(roles: &RoleSet, role: u8) -> bool {
+    ROLE_ADMINS.with(|ra| {
+        ra.borrow()
+            .get(&role)
+            .map_or(false, |admins| admins.intersects(roles))
+    })
+}
+
+/// Grants roles to a principal. Must be called by the `owner` or a `role_admin`.
+/// Returns a vector of booleans indicating whether each role was successfully granted, in the same order as the input.
+///
+/// Each role is given with an optional expiry time (in nanoseconds since the epoch), at which the grant expires.
+/// Roles without an expiry are permanent. Granting a role again replaces its expiry.
+///
+/// When a role has already been granted prior to calling this function,
+/// but the current caller has the permission to grant the role,
+/// the return value for that role is `true`.
+/// When a role has already been granted prior to calling this function,
+/// but the current caller does not have the permission to grant the role,
+/// the return value for that role is `false`.
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn grant_roles(roles: Vec<RoleGrant>, principal: Principal) -> Vec<bool> {
+    // Delegated roles do not confer role-admin authority, since grants would outlive the delegation.
+    let caller_roles = roles_of(&canister_caller());
+    let is_caller_admin = is_admin(canister_caller());
+    grant_roles_as(roles, principal, &caller_roles, is_caller_admin)
+}
+
+// Grants roles to a principal on behalf of a caller with the given roles.
+#[cfg(feature = "access-roles")]
+fn grant_roles_as(
+    roles: Vec<RoleGrant>,
+    principal: Principal,
+    caller_roles: &RoleSet,
+    is_caller_admin: bool,
+) -> Vec<bool> {
+    // caller authentication in arithmetics
+    let mut success = Vec::with_capacity(roles.len());
+    // granted roles grouped by expiry, for the audit events
+    #[cfg(feature = "audit-events")]
+    let mut granted = std::collections::BTreeMap::<Option<u64>, Vec<u8>>::new();
+    let old_roles = stored_roles(&principal);
+    let mut principal_roles = old_roles;
+    for (role, expires_at) in roles {
+        if is_caller_admin || is_role_admin(caller_roles, role) {
+            principal_roles.insert(role);
+            set_role_expiry(&principal, role, expires_at);
+            #[cfg(feature = "audit-events")]
+            granted.entry(expires_at).or_default().push(role);
+            success.push(true);
         } else {
-            Err("Unauthorized".to_string())
+            success.push(false);
+        }
+    }
+    ACCESS_ROLES.with(|ar| ar.borrow_mut().insert(principal.into(), principal_roles));
+    update_role_members(principal, old_roles, principal_roles);
+    #[cfg(feature = "audit-events")]
+    for (expires_at, roles) in granted {
+        match expires_at {
+            Some(expires_at) => emit(
+                AuditAction::RolesGrantedUntil { roles, expires_at },
+                Some(principal),
+            ),
+            None => emit(AuditAction::RolesGranted(roles), Some(principal)),
         }
+    }
+    success
+}
+
+/// Revokes roles from a principal. Must be called by the `owner` or a `role_admin`.
+/// Returns a vector of booleans indicating whether each role was successfully revoked, in the same order as the input.
+///
+/// When a role has already been revoked prior to calling this function,
+/// but the current caller has the permission to revoke the role,
+/// the return value for that role is `true`.
+/// When a role has already been revoked prior to calling this function,
+/// but the current caller does not have the permission to revoke the role,
+/// the return value for that role is `false`.
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn revoke_roles(roles: Vec<u8>, principal: Principal) -> Vec<bool> {
+    let caller_roles = roles_of(&canister_caller());
+    let is_caller_admin = is_admin(canister_caller());
+    revoke_roles_as(roles, principal, &caller_roles, is_caller_admin)
+}
+
+// Revokes roles from a principal on behalf of a caller with the given roles.
+#[cfg(feature = "access-roles")]
+fn revoke_roles_as(
+    roles: Vec<u8>,
+    principal: Principal,
+    caller_roles: &RoleSet,
+    is_caller_admin: bool,
+) -> Vec<bool> {
+    // caller authentication arithmetics
+    let mut success = Vec::with_capacity(roles.len());
+    #[cfg(feature = "audit-events")]
+    let mut revoked = Vec::new();
+    let old_roles = stored_roles(&principal);
+    let mut principal_roles = old_roles;
+    for role in roles {
+        if is_caller_admin || is_role_admin(caller_roles, role) {
+            principal_roles.remove(role);
+            set_role_expiry(&principal, role, None);
+            #[cfg(feature = "audit-events")]
+            revoked.push(role);
+            success.push(true);
+        } else {
+            success.push(false);
+        }
+    }
+    ACCESS_ROLES.with(|ar| ar.borrow_mut().insert(principal.into(), principal_roles));
+    update_role_members(principal, old_roles, principal_roles);
+    #[cfg(feature = "audit-events")]
+    if !revoked.is_empty() {
+        emit(AuditAction::RolesRevoked(revoked), Some(principal));
+    }
+    success
+}
+
+/// Number of instructions after which batch updates stop processing entries.
+/// This leaves room below the instruction limit of an update message for the last entry and the reply.
+#[cfg(feature = "access-roles")]
+pub const BATCH_INSTRUCTION_LIMIT: u64 = 10_000_000_000;
+
+// The performance counter type for instructions executed in the current message.
+#[cfg(feature = "access-roles")]
+const MESSAGE_INSTRUCTION_COUNTER: u32 = 0;
+
+/// Result of a batch update.
+/// `results` holds the result of each processed entry, in the same order as the input.
+/// `next` is the index of the first entry that was not processed because of the instruction limit,
+/// or `None` if all entries were processed. The caller can continue by resubmitting the entries from `next`.
+#[cfg(feature = "access-roles")]
+#[derive(Clone, CandidType, serde::Serialize, serde::Deserialize)]
+pub struct RolesBatchResult {
+    pub results: Vec<Vec<bool>>,
+    pub next: Option<u64>,
+}
+
+// Applies `f` to the entries until all are processed or the instruction limit is reached.
+#[cfg(feature = "access-roles")]
+fn apply_batch<R>(
+    entries: Vec<(Principal, Vec<R>)>,
+    mut f: impl FnMut(Principal, Vec<R>) -> Vec<bool>,
+) -> RolesBatchResult {
+    let mut results = Vec::with_capacity(entries.len());
+    for (i, (principal, roles)) in entries.into_iter().enumerate() {
+        if performance_counter(MESSAGE_INSTRUCTION_COUNTER) > BATCH_INSTRUCTION_LIMIT {
+            return RolesBatchResult {
+                results,
+                next: Some(i as u64),
+            };
+        }
+        results.push(f(principal, roles));
+    }
+    RolesBatchResult {
+        results,
+        next: None,
+    }
+}
+
+/// Grants roles to many principals in one message, see [`grant_roles`].
+/// Each entry is a principal and the roles to grant to it, each with an optional expiry.
+///
+/// Entries are processed in order until the [`BATCH_INSTRUCTION_LIMIT`] is reached,
+/// and the result reports where processing stopped.
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn grant_roles_batch(entries: Vec<(Principal, Vec<RoleGrant>)>) -> RolesBatchResult {
+    let caller_roles = roles_of(&canister_caller());
+    let is_caller_admin = is_admin(canister_caller());
+    apply_batch(entries, |principal, roles| {
+        grant_roles_as(roles, principal, &caller_roles, is_caller_admin)
+    })
+}
+
+/// Revokes roles from many principals in one message, see [`revoke_roles`].
+/// Each entry is a principal and the roles to revoke from it.
+///
+/// Entries are processed in order until the [`BATCH_INSTRUCTION_LIMIT`] is reached,
+/// and the result reports where processing stopped.
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn revoke_roles_batch(entries: Vec<(Principal, Vec<u8>)>) -> RolesBatchResult {
+    let caller_roles = roles_of(&canister_caller());
+    let is_caller_admin = is_admin(canister_caller());
+    apply_batch(entries, |principal, roles| {
+        revoke_roles_as(roles, principal, &caller_roles, is_caller_admin)
+    })
+}
+
+/// Returns the expiry time of a role granted to a principal, or `None` if the role is not time-bound.
+#[cfg(feature = "access-roles")]
+#[query]
+pub fn get_role_expiry(role: u8, principal: Principal) -> Option<u64> {
+    ROLE_EXPIRIES.with(|e| e.borrow().get(&(principal.into(), role)))
+}
+
+/// Removes up to `limit` expired role grants, and returns the number of grants removed.
+/// This can be called periodically, e.g. from a timer.
+#[cfg(feature = "access-roles")]
+pub fn remove_expired_roles(limit: u64) -> u64 {
+    let now = canister_time();
+    let expired: Vec<(Principal, u8)> = ROLE_EXPIRY_QUEUE.with(|q| {
+        q.borrow()
+            .iter()
+            .take_while(|((expires_at, _), _)| *expires_at <= now)
+            .take(limit as usize)
+            .map(|((_, (p, role)), _)| ((&p).into(), role))
+            .collect()
+    });
+    for (principal, role) in &expired {
+        let old_roles = stored_roles(principal);
+        let mut new_roles = old_roles;
+        new_roles.remove(*role);
+        ACCESS_ROLES.with(|ar| ar.borrow_mut().insert(principal.into(), new_roles));
+        update_role_members(*principal, old_roles, new_roles);
+        set_role_expiry(principal, *role, None);
+        #[cfg(feature = "audit-events")]
+        emit(AuditAction::RolesExpired(vec![*role]), Some(*principal));
+    }
+    expired.len() as u64
+}
+
+/// Removes up to `limit` expired role grants, and returns the number of grants removed. Must be called by admins.
+#[cfg(feature = "access-roles")]
+#[update]
+#[modifiers("only_admin")]
+pub fn prune_expired_roles(limit: u64) -> u64 {
+    unwrap_or_reject(try_prune_expired_roles(limit))
+}
+
+/// Same as [`prune_expired_roles`], but returns an error instead of rejecting the call.
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn try_prune_expired_roles(limit: u64) -> Result<u64, RusticError> {
+    check_admin()?;
+    Ok(remove_expired_roles(limit))
+}
+
+/// Returns the roles granted to a principal in ascending order, excluding delegated roles.
+#[cfg(feature = "access-roles")]
+#[query]
+pub fn get_user_roles(principal: Principal) -> Vec<u8> {
+    roles_of(&principal).roles().collect()
+}
+
+/// Returns a page of at most `limit` members of a role, in ascending order starting from `start`.
+/// Members whose grant has expired are excluded.
+#[cfg(feature = "access-roles")]
+#[query]
+pub fn get_role_members(role: u8, start: Option<Principal>, limit: u64) -> RoleMembersPage {
+    let start = start.unwrap_or(Principal::management_canister());
+    let limit = limit.min(MAX_ROLE_MEMBERS_PAGE_SIZE) as usize;
+    ROLE_MEMBERS.with(|m| {
+        let m = m.borrow();
+        let now = canister_time();
+        let mut members: Vec<Principal> = m
+            .range((role, start.into())..)
+            .take_while(|((r, _), _)| *r == role)
+            .filter(|((_, p), _)| !is_grant_expired(*p, role, now))
+            .take(limit + 1)
+            .map(|((_, p), _)| (&p).into())
+            .collect();
+        let next = (members.len() > limit).then(|| members.pop()).flatten();
+        RoleMembersPage { members, next }
+    })
+}
+
+/// Returns the number of members of a role, excluding expired grants.
+/// Expired grants that have not been removed yet are subtracted, so pruning keeps this cheap.
+#[cfg(feature = "access-roles")]
+#[query]
+pub fn get_role_member_count(role: u8) -> u64 {
+    let now = canister_time();
+    let expired = ROLE_EXPIRY_QUEUE.with(|q| {
+        q.borrow()
+            .iter()
+            .take_while(|((expires_at, _), _)| *expires_at <= now)
+            .filter(|((_, (_, r)), _)| *r == role)
+            .count() as u64
+    });
+    ROLE_MEMBER_COUNTS
+        .with(|c| c.borrow().get(&role).unwrap_or(0))
+        .saturating_sub(expired)
+}
+
+/// Maximum duration of a role delegation in nanoseconds (30 days).
+#[cfg(feature = "access-roles")]
+pub const MAX_DELEGATION_DURATION: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+/// A delegation of roles from a role holder to a deputy.
+#[cfg(feature = "access-roles")]
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, serde::Serialize, serde::Deserialize)]
+pub struct RoleDelegation {
+    pub delegator: Principal,
+    pub delegate: Principal,
+    pub roles: Vec<u8>,
+    /// Time in nanoseconds since the epoch at which the delegation ends.
+    pub expires_at: u64,
+}
+
+/// Delegates roles of the caller to a deputy until `expires_at`, replacing any previous delegation to the deputy.
+/// The caller must have all the roles, and `expires_at` must be within [`MAX_DELEGATION_DURATION`] from now.
+///
+/// The deputy passes role checks such as [`has_role`] for the delegated roles as long as
+/// the delegation has not expired and the caller still has the roles.
+/// Delegated roles cannot be delegated further, do not allow granting or revoking roles as a role admin,
+/// and are not listed by [`get_user_roles`] and [`get_role_members`].
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn delegate_roles(roles: Vec<u8>, delegate: Principal, expires_at: u64) {
+    unwrap_or_reject(try_delegate_roles(roles, delegate, expires_at))
+}
+
+/// Same as [`delegate_roles`], but returns an error instead of rejecting the call.
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn try_delegate_roles(
+    roles: Vec<u8>,
+    delegate: Principal,
+    expires_at: u64,
+) -> Result<(), RusticError> {
+    let delegator = canister_caller();
+    check_principal_not_anonymous(&Some(delegate))?;
+    if delegate == delegator {
+        return Err(RusticError::SelfDelegation);
+    }
+    let now = canister_time();
+    if expires_at <= now || expires_at - now > MAX_DELEGATION_DURATION {
+        return Err(RusticError::InvalidExpiry);
+    }
+    let delegator_roles = roles_of(&delegator);
+    if let Some(role) = roles.iter().find(|role| !delegator_roles.contains(**role)) {
+        return Err(RusticError::MissingRole(*role));
+    }
+    let role_set: RoleSet = roles.iter().copied().collect();
+    ROLE_DELEGATIONS.with(|d| {
+        d.borrow_mut()
+            .insert((delegate.into(), delegator.into()), (role_set, expires_at))
+    });
+    ROLE_DELEGATORS.with(|d| {
+        d.borrow_mut()
+            .insert((delegator.into(), delegate.into()), ())
+    });
+    #[cfg(feature = "audit-events")]
+    emit(
+        AuditAction::RolesDelegated { roles, expires_at },
+        Some(delegate),
+    );
+    Ok(())
+}
+
+/// Revokes the delegation of the caller to a deputy. Does nothing if there is no such delegation.
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn revoke_delegation(delegate: Principal) {
+    unwrap_or_reject(try_revoke_delegation(delegate))
+}
+
+/// Same as [`revoke_delegation`], but returns an error instead of rejecting the call.
+/// Revoking a delegation cannot currently fail, so this always returns `Ok`.
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn try_revoke_delegation(delegate: Principal) -> Result<(), RusticError> {
+    let delegator = canister_caller();
+    if ROLE_DELEGATIONS
+        .with(|d| d.borrow_mut().remove(&(delegate.into(), delegator.into())))
+        .is_none()
+    {
+        return Ok(());
+    }
+    ROLE_DELEGATORS.with(|d| d.borrow_mut().remove(&(delegator.into(), delegate.into())));
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::DelegationRevoked, Some(delegate));
+    Ok(())
+}
+
+#[cfg(feature = "access-roles")]
+fn delegation(delegator: Principal, delegate: Principal) -> Option<RoleDelegation> {
+    ROLE_DELEGATIONS
+        .with(|d| d.borrow().get(&(delegate.into(), delegator.into())))
+        .map(|(roles, expires_at)| RoleDelegation {
+            delegator,
+            delegate,
+            roles: roles.roles().collect(),
+            expires_at,
+        })
+}
+
+/// Returns the unexpired delegations made by a principal, ordered by delegate.
+#[cfg(feature = "access-roles")]
+#[query]
+pub fn get_delegations_by(delegator: Principal) -> Vec<RoleDelegation> {
+    let now = canister_time();
+    let start: StablePrincipal = delegator.into();
+    let delegates: Vec<Principal> = ROLE_DELEGATORS.with(|d| {
+        d.borrow()
+            .range(
+                (
+                    start,
+                    StablePrincipal::from(Principal::management_canister()),
+                )..,
+            )
+            .take_while(|((p, _), _)| *p == start)
+            .map(|((_, delegate), _)| (&delegate).into())
+            .collect()
+    });
+    delegates
+        .into_iter()
+        .filter_map(|delegate| delegation(delegator, delegate))
+        .filter(|d| now < d.expires_at)
+        .collect()
+}
+
+/// Returns the unexpired delegations made to a principal, ordered by delegator.
+#[cfg(feature = "access-roles")]
+#[query]
+pub fn get_delegations_to(delegate: Principal) -> Vec<RoleDelegation> {
+    let now = canister_time();
+    let start: StablePrincipal = delegate.into();
+    ROLE_DELEGATIONS.with(|d| {
+        d.borrow()
+            .range(
+                (
+                    start,
+                    StablePrincipal::from(Principal::management_canister()),
+                )..,
+            )
+            .take_while(|((p, _), _)| *p == start)
+            .filter(|(_, (_, expires_at))| now < *expires_at)
+            .map(|((_, delegator), (roles, expires_at))| RoleDelegation {
+                delegator: (&delegator).into(),
+                delegate,
+                roles: roles.roles().collect(),
+                expires_at,
+            })
+            .collect()
     })
 }
 
+/// Checks whether a principal has a certain role.
+/// Returns a boolean indicating whether the principal has the role.
+#[cfg(feature = "access-roles")]
+#[query]
+pub fn user_has_role(role: u8, principal: Principal) -> bool {
+    effective_roles(&principal).contains(role)
+}
+
+/// Checks whether the caller has a certain role.
+/// This is typically used in conjunction with the [`modifiers`] macro.
+#[cfg(feature = "access-roles")]
+pub fn has_role(role: u8) -> Result<(), String> {
+    Ok(check_role(role)?)
+}
+
+/// Checks whether the caller has a certain role, returning a typed error.
+#[cfg(feature = "access-roles")]
+pub fn check_role(role: u8) -> Result<(), RusticError> {
+    if effective_roles(&canister_caller()).contains(role) {
+        Ok(())
+    } else {
+        Err(RusticError::MissingRole(role))
+    }
+}
+
 /// Checks whether a principal has all of the specified roles.
 /// Returns a boolean indicating whether the principal has all of the roles.
 #[cfg(feature = "access-roles")]
 #[query]
 pub fn user_has_roles_all(roles: Vec<u8>, principal: Principal) -> bool {
-    ACCESS_ROLES.with(|c| {
-        let c = c.borrow();
-        let principal_roles = c.get(&principal.into()).unwrap_or(0);
-        roles.iter().all(|role| principal_roles & (1 << role) != 0)
-    })
+    let principal_roles = effective_roles(&principal);
+    roles.iter().all(|role| principal_roles.contains(*role))
 }
 
 /// Checks whether the caller has all of the specified roles.
 /// This is typically used in conjunction with the [`modifiers`] macro.
 #[cfg(feature = "access-roles")]
 pub fn has_roles_all(roles: Vec<u8>) -> Result<(), String> {
-    ACCESS_ROLES.with(|c| {
-        let c = c.borrow();
-        let principal_roles = c.get(&canister_caller().into()).unwrap_or(0);
-        if roles.iter().all(|role| principal_roles & (1 << role) != 0) {
-            Ok(())
-        } else {
-            Err("Unauthorized".to_string())
-        }
-    })
+    Ok(check_roles_all(roles)?)
+}
+
+/// Checks whether the caller has all of the specified roles, returning the missing roles as a typed error.
+#[cfg(feature = "access-roles")]
+pub fn check_roles_all(roles: Vec<u8>) -> Result<(), RusticError> {
+    let caller_roles = effective_roles(&canister_caller());
+    let missing: Vec<u8> = roles
+        .into_iter()
+        .filter(|role| !caller_roles.contains(*role))
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(RusticError::MissingRoles(missing))
+    }
 }
 
 /// Checks whether a principal has any of the specified roles.
@@ -466,62 +1448,337 @@ pub fn has_roles_all(roles: Vec<u8>) -> Result<(), String> {
 #[cfg(feature = "access-roles")]
 #[query]
 pub fn user_has_roles_any(roles: Vec<u8>, principal: Principal) -> bool {
-    ACCESS_ROLES.with(|c| {
-        let c = c.borrow();
-        let principal_roles = c.get(&principal.into()).unwrap_or(0);
-        roles.iter().any(|role| principal_roles & (1 << role) != 0)
-    })
+    let principal_roles = effective_roles(&principal);
+    roles.iter().any(|role| principal_roles.contains(*role))
 }
 
 /// Checks whether the caller has any of the specified roles.
 /// This is typically used in conjunction with the [`modifiers`] macro.
 #[cfg(feature = "access-roles")]
 pub fn has_roles_any(roles: Vec<u8>) -> Result<(), String> {
-    ACCESS_ROLES.with(|c| {
-        let c = c.borrow();
-        let principal_roles = c.get(&canister_caller().into()).unwrap_or(0);
-        if roles.iter().any(|role| principal_roles & (1 << role) != 0) {
-            Ok(())
-        } else {
-            Err("Unauthorized".to_string())
+    Ok(check_roles_any(roles)?)
+}
+
+/// Checks whether the caller has any of the specified roles, returning a typed error.
+#[cfg(feature = "access-roles")]
+pub fn check_roles_any(roles: Vec<u8>) -> Result<(), RusticError> {
+    if user_has_roles_any(roles.clone(), canister_caller()) {
+        Ok(())
+    } else {
+        Err(RusticError::MissingRoles(roles))
+    }
+}
+
+/// Sets role admins for a role. Must be called by admins.
+#[cfg(feature = "access-roles")]
+#[update]
+#[modifiers("only_admin")]
+#[cfg_attr(feature = "multisig", modifiers("when_multisig_disabled"))]
+pub fn set_role_admins(role: u8, admins: Vec<u8>) {
+    unwrap_or_reject(try_set_role_admins(role, admins))
+}
+
+/// Same as [`set_role_admins`], but returns an error instead of rejecting the call.
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn try_set_role_admins(role: u8, admins: Vec<u8>) -> Result<(), RusticError> {
+    check_admin()?;
+    #[cfg(feature = "multisig")]
+    check_multisig_disabled()?;
+    add_role_admins(role, admins);
+    Ok(())
+}
+
+#[cfg(feature = "access-roles")]
+pub(crate) fn add_role_admins(role: u8, admins: Vec<u8>) {
+    let admin_roles: RoleSet = admins.iter().copied().collect();
+    ROLE_ADMINS.with(|ra| {
+        let mut ra = ra.borrow_mut();
+        let current = ra.get(&role).unwrap_or_default();
+        ra.insert(role, current.union(&admin_roles));
+    });
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::RoleAdminsSet { role, admins }, None);
+}
+
+/// Revokes role admins for a role. Must be called by admins.
+#[cfg(feature = "access-roles")]
+#[update]
+#[modifiers("only_admin")]
+#[cfg_attr(feature = "multisig", modifiers("when_multisig_disabled"))]
+pub fn revoke_role_admins(role: u8, admins: Vec<u8>) {
+    unwrap_or_reject(try_revoke_role_admins(role, admins))
+}
+
+/// Same as [`revoke_role_admins`], but returns an error instead of rejecting the call.
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn try_revoke_role_admins(role: u8, admins: Vec<u8>) -> Result<(), RusticError> {
+    check_admin()?;
+    #[cfg(feature = "multisig")]
+    check_multisig_disabled()?;
+    remove_role_admins(role, admins);
+    Ok(())
+}
+
+#[cfg(feature = "access-roles")]
+pub(crate) fn remove_role_admins(role: u8, admins: Vec<u8>) {
+    let admin_roles: RoleSet = admins.iter().copied().collect();
+    ROLE_ADMINS.with(|ra| {
+        let mut ra = ra.borrow_mut();
+        let current = ra.get(&role).unwrap_or_default();
+        ra.insert(role, current.difference(&admin_roles));
+    });
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::RoleAdminsRevoked { role, admins }, None);
+}
+
+/// Defines the name and description of a role, replacing any previous definition. Must be called by admins.
+/// Rejects the call if the name is empty or already used by another role.
+#[cfg(feature = "access-roles")]
+#[update]
+#[modifiers("only_admin")]
+pub fn define_role(role: u8, name: String, description: String) {
+    unwrap_or_reject(try_define_role(role, name, description))
+}
+
+/// Same as [`define_role`], but returns an error instead of rejecting the call.
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn try_define_role(role: u8, name: String, description: String) -> Result<(), RusticError> {
+    check_admin()?;
+    if name.is_empty() {
+        return Err(RusticError::EmptyRoleName);
+    }
+    if role_by_name(&name).map_or(false, |r| r != role) {
+        return Err(RusticError::RoleNameTaken(name));
+    }
+    ROLE_REGISTRY.with(|r| {
+        r.borrow_mut().insert(
+            role,
+            Cbor(RoleInfo {
+                name: name.clone(),
+                description,
+            }),
+        )
+    });
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::RoleDefined { role, name }, None);
+    Ok(())
+}
+
+/// Removes the definition of a role. Must be called by admins.
+/// The members of the role are not affected.
+#[cfg(feature = "access-roles")]
+#[update]
+#[modifiers("only_admin")]
+pub fn undefine_role(role: u8) {
+    unwrap_or_reject(try_undefine_role(role))
+}
+
+/// Same as [`undefine_role`], but returns an error instead of rejecting the call.
+#[cfg(feature = "access-roles")]
+#[update]
+pub fn try_undefine_role(role: u8) -> Result<(), RusticError> {
+    check_admin()?;
+    ROLE_REGISTRY.with(|r| r.borrow_mut().remove(&role));
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::RoleUndefined(role), None);
+    Ok(())
+}
+
+/// Returns all defined roles with their names and descriptions, ordered by role.
+#[cfg(feature = "access-roles")]
+#[query]
+pub fn get_roles() -> Vec<(u8, RoleInfo)> {
+    ROLE_REGISTRY.with(|r| r.borrow().iter().map(|(k, v)| (k, v.0)).collect())
+}
+
+/// Returns the role with the given name, if defined.
+#[cfg(feature = "access-roles")]
+#[query]
+pub fn get_role_by_name(name: String) -> Option<u8> {
+    role_by_name(&name)
+}
+
+#[cfg(feature = "access-roles")]
+fn role_by_name(name: &str) -> Option<u8> {
+    ROLE_REGISTRY.with(|r| {
+        r.borrow()
+            .iter()
+            .find(|(_, info)| info.name == name)
+            .map(|(role, _)| role)
+    })
+}
+
+/// Checks whether the caller has the role with the given name.
+/// Fails if no role is defined with that name.
+/// This is typically used in conjunction with the [`modifiers`] macro.
+/// # Example
+/// ```rust
+/// # use ic_cdk::update;
+/// # use rustic_macros::modifiers;
+/// # use rustic::access_control::has_role_named;
+/// #[update]
+/// #[modifiers("has_role_named@\"minter\"")]
+/// fn mint() {}
+/// ```
+#[cfg(feature = "access-roles")]
+pub fn has_role_named(name: &str) -> Result<(), String> {
+    Ok(check_role_named(name)?)
+}
+
+/// Checks whether the caller has the role with the given name, returning a typed error.
+#[cfg(feature = "access-roles")]
+pub fn check_role_named(name: &str) -> Result<(), RusticError> {
+    match role_by_name(name) {
+        Some(role) => check_role(role),
+        None => Err(RusticError::RoleNotDefined(name.to_string())),
+    }
+}
+
+// Guard combinators
+
+/// A guard value that can be combined with [`any_of`], [`all_of`] and [`not`]
+/// into boolean expressions over the existing guards.
+///
+/// A combined guard is checked with the [`guard`] guard. The `modifiers` macro splits its parameters at commas,
+/// so the expression is best built in a function:
+/// ```rust
+/// # use ic_cdk::update;
+/// # use rustic::access_control::{any_of, guard, Guard};
+/// # use rustic::error::RusticError;
+/// # use rustic_macros::modifiers;
+/// fn check_minter() -> Result<(), RusticError> {
+///     // application specific check
+/// #   Ok(())
+/// }
+///
+/// fn owner_or_minter() -> Guard {
+///     any_of([Guard::owner(), Guard::new("minter", check_minter)])
+/// }
+///
+/// #[update]
+/// #[modifiers("guard@owner_or_minter()")]
+/// fn mint() {}
+/// ```
+pub struct Guard {
+    name: String,
+    check: Box<dyn Fn() -> Result<(), RusticError>>,
+}
+
+impl Guard {
+    /// Creates a guard from a check. The name is used in the error of [`not`].
+    pub fn new(
+        name: impl Into<String>,
+        check: impl Fn() -> Result<(), RusticError> + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            check: Box::new(check),
+        }
+    }
+
+    /// Returns the name of the guard, e.g. `any_of(owner, role 3)`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Checks the guard.
+    pub fn check(&self) -> Result<(), RusticError> {
+        (self.check)()
+    }
+
+    /// Passes if the caller is the owner, see [`only_owner`].
+    pub fn owner() -> Self {
+        Self::new("owner", check_owner)
+    }
+
+    /// Passes if the caller is an admin, see [`only_admin`].
+    pub fn admin() -> Self {
+        Self::new("admin", check_admin)
+    }
+
+    /// Passes if the caller has the role, see [`has_role`].
+    #[cfg(feature = "access-roles")]
+    pub fn role(role: u8) -> Self {
+        Self::new(format!("role {role}"), move || check_role(role))
+    }
+
+    /// Passes if the caller has all of the roles, see [`has_roles_all`].
+    #[cfg(feature = "access-roles")]
+    pub fn roles_all(roles: Vec<u8>) -> Self {
+        Self::new(format!("all roles {roles:?}"), move || {
+            check_roles_all(roles.clone())
+        })
+    }
+
+    /// Passes if the caller has any of the roles, see [`has_roles_any`].
+    #[cfg(feature = "access-roles")]
+    pub fn roles_any(roles: Vec<u8>) -> Self {
+        Self::new(format!("any role {roles:?}"), move || {
+            check_roles_any(roles.clone())
+        })
+    }
+
+    /// Passes if the caller has the role with the given name, see [`has_role_named`].
+    #[cfg(feature = "access-roles")]
+    pub fn role_named(name: &str) -> Self {
+        let name = name.to_string();
+        Self::new(format!("role {name}"), move || check_role_named(&name))
+    }
+}
+
+/// Passes if any of the guards passes. Fails with [`RusticError::NoneOf`] holding the error of each guard.
+pub fn any_of(guards: impl IntoIterator<Item = Guard>) -> Guard {
+    let guards: Vec<Guard> = guards.into_iter().collect();
+    let name = format!(
+        "any_of({})",
+        guards
+            .iter()
+            .map(Guard::name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Guard::new(name, move || {
+        let mut errors = Vec::with_capacity(guards.len());
+        for guard in &guards {
+            match guard.check() {
+                Ok(()) => return Ok(()),
+                Err(e) => errors.push(e),
+            }
         }
+        Err(RusticError::NoneOf(errors))
     })
 }
 
-/// Sets role admins for a role. Must be called by admins.
-#[cfg(feature = "access-roles")]
-#[update]
-#[modifiers("only_admin")]
-pub fn set_role_admins(role: u8, admins: Vec<u8>) {
-    let admin_bit_flags = admins.iter().fold(0, |acc, x| acc | (1 << x));
-    ACCESS_CONTROL.with(|c| {
-        let mut c = c.borrow_mut();
-        #[allow(clippy::unwrap_used)] // unwrap desired
-        let mut config = c.get().0.clone().unwrap();
-        if let Some(x) = config.admins_of_role.get_mut(role as usize) {
-            *x |= admin_bit_flags;
-        }
-        #[allow(clippy::expect_used)] // unwrap desired
-        c.set(Cbor(Some(config))).expect("Set role admin failed");
-    });
+/// Passes if all of the guards pass. Fails with the error of the first failing guard.
+pub fn all_of(guards: impl IntoIterator<Item = Guard>) -> Guard {
+    let guards: Vec<Guard> = guards.into_iter().collect();
+    let name = format!(
+        "all_of({})",
+        guards
+            .iter()
+            .map(Guard::name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Guard::new(name, move || guards.iter().try_for_each(Guard::check))
 }
 
-/// Revokes role admins for a role. Must be called by admins.
-#[cfg(feature = "access-roles")]
-#[update]
-#[modifiers("only_admin")]
-pub fn revoke_role_admins(role: u8, admins: Vec<u8>) {
-    let admin_bit_flags = admins.iter().fold(0, |acc, x| acc | (1 << x));
-    ACCESS_CONTROL.with(|c| {
-        let mut c = c.borrow_mut();
-        #[allow(clippy::unwrap_used)] // unwrap desired
-        let mut config = c.get().0.clone().unwrap();
-        if let Some(x) = config.admins_of_role.get_mut(role as usize) {
-            *x &= !admin_bit_flags;
-        }
-        #[allow(clippy::expect_used)] // unwrap desired
-        c.set(Cbor(Some(config))).expect("Revoke role admin failed");
-    });
+/// Passes if the guard fails. Fails with [`RusticError::Negated`] holding the name of the guard.
+pub fn not(guard: Guard) -> Guard {
+    let name = format!("not({})", guard.name());
+    Guard::new(name, move || match guard.check() {
+        Ok(()) => Err(RusticError::Negated(guard.name().to_string())),
+        Err(_) => Ok(()),
+    })
+}
+
+/// Checks a guard built with the guard combinators.
+/// This is typically used in conjunction with the [`modifiers`] macro, see [`Guard`].
+pub fn guard(guard: Guard) -> Result<(), String> {
+    Ok(guard.check()?)
 }
 
 #[cfg(test)]
@@ -588,6 +1845,192 @@ mod access_tests {
         assert!(only_owner().is_ok());
     }
 
+    #[test]
+    fn test_ownable_transfer_ownership_deadline() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_mock_time(1_000);
+        set_ownership_transfer_timeout(500);
+        assert_eq!(ownership_transfer_timeout(), 500);
+        transfer_ownership(Some(Principal::from_text(MOCK_USER_1).unwrap()));
+        assert_eq!(pending_owner_proposed_at(), Some(1_000));
+        assert_eq!(
+            owner_and_pending_owner(),
+            (
+                Principal::from_text(MOCK_USER_0).ok(),
+                Principal::from_text(MOCK_USER_1).ok(),
+                Some(1_500)
+            )
+        );
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        set_mock_time(1_500);
+        accept_ownership();
+        assert!(only_owner().is_ok());
+        assert_eq!(
+            owner_and_pending_owner(),
+            (Principal::from_text(MOCK_USER_1).ok(), None, None)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Pending ownership transfer has expired")]
+    fn test_ownable_transfer_ownership_expired() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_mock_time(1_000);
+        transfer_ownership(Some(Principal::from_text(MOCK_USER_1).unwrap()));
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        set_mock_time(1_001 + DEFAULT_OWNERSHIP_TRANSFER_TIMEOUT);
+        accept_ownership();
+    }
+
+    #[test]
+    fn test_try_ownership_errors() {
+        let user0 = Principal::from_text(MOCK_USER_0).unwrap();
+        let user1 = Principal::from_text(MOCK_USER_1).unwrap();
+        set_mock_caller(user0);
+        access_init(canister_caller());
+        set_mock_time(1_000);
+        assert_eq!(try_accept_ownership(), Err(RusticError::NoPendingOwner));
+        assert_eq!(
+            try_transfer_ownership(Some(Principal::anonymous())),
+            Err(RusticError::AnonymousPrincipal)
+        );
+        assert_eq!(try_transfer_ownership(Some(user1)), Ok(()));
+        assert_eq!(try_accept_ownership(), Err(RusticError::NotPendingOwner));
+        set_mock_caller(user1);
+        assert_eq!(
+            try_renounce_ownership_confirmation(),
+            Err(RusticError::NotOwner)
+        );
+        assert_eq!(
+            try_renounce_ownership(String::new()),
+            Err(RusticError::NotOwner)
+        );
+        assert_eq!(try_grant_admin(user1), Err(RusticError::NotOwner));
+        assert_eq!(try_renounce_admin(), Err(RusticError::NotAdmin));
+        set_mock_time(1_001 + DEFAULT_OWNERSHIP_TRANSFER_TIMEOUT);
+        assert_eq!(
+            try_accept_ownership(),
+            Err(RusticError::OwnershipTransferExpired)
+        );
+        assert_eq!(owner(), Some(user0));
+        assert_eq!(pending_owner(), Some(user1));
+    }
+
+    #[test]
+    fn test_lockout_protection() {
+        let user0 = Principal::from_text(MOCK_USER_0).unwrap();
+        let user1 = Principal::from_text(MOCK_USER_1).unwrap();
+        set_mock_caller(user0);
+        access_init(canister_caller());
+        assert_eq!(try_revoke_admin(user0), Err(RusticError::LastAdmin));
+        assert_eq!(try_renounce_admin(), Err(RusticError::LastAdmin));
+        assert_eq!(
+            try_transfer_ownership_immediate(None),
+            Err(RusticError::NoOwner)
+        );
+        assert_eq!(
+            try_renounce_ownership("renounce ownership".to_string()),
+            Err(RusticError::InvalidConfirmation)
+        );
+        assert_eq!(owner(), Some(user0));
+
+        grant_admin(user1);
+        renounce_admin();
+        assert!(!is_admin(user0));
+        renounce_ownership(renounce_ownership_confirmation());
+        assert_eq!(owner(), None);
+        assert!(is_admin(user1));
+    }
+
+    #[test]
+    fn test_renounce_confirmation() {
+        let user0 = Principal::from_text(MOCK_USER_0).unwrap();
+        let user1 = Principal::from_text(MOCK_USER_1).unwrap();
+        set_mock_caller(user0);
+        set_mock_time(0);
+        access_init(canister_caller());
+
+        // a new token invalidates the previous one
+        let token = renounce_ownership_confirmation();
+        let token2 = renounce_ownership_confirmation();
+        assert_ne!(token, token2);
+        assert_eq!(
+            try_renounce_ownership(token),
+            Err(RusticError::InvalidConfirmation)
+        );
+
+        set_mock_time(RENOUNCE_CONFIRMATION_TIMEOUT + 1);
+        assert_eq!(
+            try_renounce_ownership(token2),
+            Err(RusticError::ConfirmationExpired)
+        );
+
+        // the token is bound to the owner
+        let token = renounce_ownership_confirmation();
+        transfer_ownership_immediate(Some(user1));
+        set_mock_caller(user1);
+        assert_eq!(
+            try_renounce_ownership(token.clone()),
+            Err(RusticError::InvalidConfirmation)
+        );
+        // a change of ownership invalidates the token, even if the owner is the same again
+        transfer_ownership_immediate(Some(user0));
+        set_mock_caller(user0);
+        assert_eq!(
+            try_renounce_ownership(token),
+            Err(RusticError::InvalidConfirmation)
+        );
+        assert_eq!(owner(), Some(user0));
+
+        let token = renounce_ownership_confirmation();
+        assert_eq!(try_renounce_ownership(token), Ok(()));
+        assert_eq!(owner(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "msg_reject should only be called inside canisters")]
+    fn test_renounce_confirmation_unauth() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        renounce_ownership_confirmation();
+    }
+
+    #[test]
+    fn test_guard_combinators() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        assert!(guard(any_of([Guard::owner(), not(Guard::admin())])).is_ok());
+        assert!(all_of([Guard::owner(), Guard::admin()]).check().is_ok());
+        assert_eq!(
+            not(Guard::owner()).check(),
+            Err(RusticError::Negated("owner".to_string()))
+        );
+
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        let g = any_of([
+            Guard::owner(),
+            all_of([not(Guard::owner()), Guard::admin()]),
+        ]);
+        assert_eq!(g.name(), "any_of(owner, all_of(not(owner), admin))");
+        assert_eq!(
+            g.check(),
+            Err(RusticError::NoneOf(vec![
+                RusticError::NotOwner,
+                RusticError::NotAdmin
+            ]))
+        );
+        assert_eq!(
+            guard(g),
+            Err(
+                "None of the guards passed: [Caller is not the owner; Caller is not an admin]"
+                    .to_string()
+            )
+        );
+    }
+
     #[test]
     fn test_admin() {
         set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
@@ -614,6 +2057,7 @@ mod access_tests {
     fn test_revoke_admin() {
         set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
         access_init(canister_caller());
+        grant_admin(Principal::from_text(MOCK_USER_1).unwrap());
         assert!(is_admin(Principal::from_text(MOCK_USER_0).unwrap()));
         revoke_admin(Principal::from_text(MOCK_USER_0).unwrap());
         assert!(!is_admin(Principal::from_text(MOCK_USER_0).unwrap()));
@@ -633,6 +2077,56 @@ mod access_tests {
         assert!(!is_admin(Principal::from_text(MOCK_USER_1).unwrap()));
     }
 
+    #[test]
+    fn test_get_admins() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        let users =
+            [MOCK_USER_1, MOCK_USER_2, MOCK_USER_3].map(|u| Principal::from_text(u).unwrap());
+        for user in users {
+            grant_admin(user);
+        }
+        grant_admin(users[0]);
+        assert_eq!(get_admin_count(), 4);
+
+        let page = get_admins(None, 3);
+        assert_eq!(page.admins.len(), 3);
+        let last = get_admins(page.next, 3);
+        assert_eq!(last.admins.len(), 1);
+        assert_eq!(last.next, None);
+        let mut all = [page.admins, last.admins].concat();
+        all.sort();
+        let mut expected = vec![canister_caller(), users[0], users[1], users[2]];
+        expected.sort();
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    fn test_admins_migrate() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        let user1 = Principal::from_text(MOCK_USER_1).unwrap();
+        ACCESS_CONTROL.with(|c| {
+            let mut c = c.borrow_mut();
+            let mut config = c.get().0.clone().unwrap();
+            config.legacy_admins = vec![canister_caller(), user1];
+            c.set(Cbor(Some(config))).unwrap();
+        });
+
+        admins_migrate();
+        admins_migrate();
+        assert!(is_admin(user1));
+        assert_eq!(get_admin_count(), 2);
+        assert!(ACCESS_CONTROL.with(|c| c
+            .borrow()
+            .get()
+            .0
+            .clone()
+            .unwrap()
+            .legacy_admins
+            .is_empty()));
+    }
+
     #[test]
     #[should_panic(expected = "msg_reject should only be called inside canisters")]
     fn grant_admin_unauth() {
@@ -721,7 +2215,7 @@ mod access_role_tests {
         assert!(is_admin(canister_caller()));
 
         grant_roles(
-            vec![Role::R0.into(), Role::R1.into()],
+            vec![(Role::R0.into(), None), (Role::R1.into(), None)],
             Principal::from_text(MOCK_USER_1).unwrap(),
         );
 
@@ -742,4 +2236,391 @@ mod access_role_tests {
             Principal::from_text(MOCK_USER_1).unwrap()
         ));
     }
+
+    #[test]
+    fn test_role_registry() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        define_role(
+            Role::R1.into(),
+            "minter".to_string(),
+            "Can mint".to_string(),
+        );
+        define_role(
+            Role::R0.into(),
+            "burner".to_string(),
+            "Can burn".to_string(),
+        );
+        assert_eq!(
+            get_role_by_name("minter".to_string()),
+            Some(Role::R1.into())
+        );
+        assert_eq!(get_role_by_name("unknown".to_string()), None);
+        let roles = get_roles();
+        assert_eq!(roles.len(), 2);
+        assert_eq!(roles[0].0, 0);
+        assert_eq!(roles[0].1.name, "burner");
+        assert_eq!(roles[1].1.description, "Can mint");
+
+        assert!(has_role_named("minter").is_err());
+        grant_roles(vec![(Role::R1.into(), None)], canister_caller());
+        assert!(has_role_named("minter").is_ok());
+        assert!(has_role_named("burner").is_err());
+        assert!(has_role_named("unknown").is_err());
+
+        undefine_role(Role::R1.into());
+        assert!(has_role_named("minter").is_err());
+        assert!(has_role(Role::R1.into()).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "Role name is already defined")]
+    fn test_role_registry_duplicate_name() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        define_role(Role::R0.into(), "minter".to_string(), String::new());
+        define_role(Role::R1.into(), "minter".to_string(), String::new());
+    }
+
+    #[test]
+    fn test_try_role_errors() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        grant_roles(vec![(1, None)], canister_caller());
+        assert_eq!(check_role(2), Err(RusticError::MissingRole(2)));
+        assert_eq!(
+            check_roles_all(vec![1, 2, 3]),
+            Err(RusticError::MissingRoles(vec![2, 3]))
+        );
+        assert_eq!(check_roles_any(vec![1, 2]), Ok(()));
+        assert_eq!(
+            check_role_named("minter"),
+            Err(RusticError::RoleNotDefined("minter".to_string()))
+        );
+        assert_eq!(
+            has_role_named("minter"),
+            Err("Role minter is not defined".to_string())
+        );
+        assert_eq!(
+            try_define_role(1, String::new(), String::new()),
+            Err(RusticError::EmptyRoleName)
+        );
+        assert_eq!(
+            try_define_role(1, "minter".to_string(), String::new()),
+            Ok(())
+        );
+        assert_eq!(
+            try_define_role(2, "minter".to_string(), String::new()),
+            Err(RusticError::RoleNameTaken("minter".to_string()))
+        );
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        assert_eq!(try_undefine_role(1), Err(RusticError::NotAdmin));
+        assert_eq!(try_set_role_admins(1, vec![2]), Err(RusticError::NotAdmin));
+        assert_eq!(try_prune_expired_roles(10), Err(RusticError::NotAdmin));
+        assert_eq!(try_revoke_delegation(canister_caller()), Ok(()));
+        assert_eq!(get_role_by_name("minter".to_string()), Some(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "msg_reject should only be called inside canisters")]
+    #[modifiers("has_role_named@\"minter\"")]
+    fn test_syntax_role_modifiers_named() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+    }
+
+    #[test]
+    fn test_role_admins() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        let user1 = Principal::from_text(MOCK_USER_1).unwrap();
+        let user2 = Principal::from_text(MOCK_USER_2).unwrap();
+        set_role_admins(200, vec![100, 150]);
+        grant_roles(vec![(150, None)], user1);
+        assert_eq!(get_user_roles(user1), vec![150]);
+
+        set_mock_caller(user1);
+        assert_eq!(
+            grant_roles(vec![(200, None), (201, None)], user2),
+            vec![true, false]
+        );
+        assert!(user_has_role(200, user2));
+        assert!(!user_has_role(201, user2));
+        assert!(user_has_roles_any(vec![0, 200], user2));
+        assert!(!user_has_roles_all(vec![0, 200], user2));
+
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        revoke_role_admins(200, vec![150]);
+        set_mock_caller(user1);
+        assert_eq!(revoke_roles(vec![200], user2), vec![false]);
+        assert!(user_has_role(200, user2));
+    }
+
+    #[test]
+    fn test_access_roles_migrate() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        let user1 = Principal::from_text(MOCK_USER_1).unwrap();
+        let user2 = Principal::from_text(MOCK_USER_2).unwrap();
+        LEGACY_ACCESS_ROLES.with(|ar| ar.borrow_mut().insert(user1.into(), 0b1001));
+        ACCESS_CONTROL.with(|c| {
+            let mut c = c.borrow_mut();
+            let mut config = c.get().0.clone().unwrap();
+            config.legacy_admins_of_role[5] = 0b1000;
+            c.set(Cbor(Some(config))).unwrap();
+        });
+
+        access_roles_migrate();
+        role_members_init();
+        access_roles_migrate();
+        assert_eq!(get_user_roles(user1), vec![0, 3]);
+        assert_eq!(get_role_member_count(3), 1);
+        assert!(LEGACY_ACCESS_ROLES.with(|ar| ar.borrow().is_empty()));
+
+        set_mock_caller(user1);
+        assert_eq!(
+            grant_roles(vec![(5, None), (6, None)], user2),
+            vec![true, false]
+        );
+    }
+
+    #[test]
+    fn test_roles_batch() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        let user1 = Principal::from_text(MOCK_USER_1).unwrap();
+        let user2 = Principal::from_text(MOCK_USER_2).unwrap();
+        let user3 = Principal::from_text(MOCK_USER_3).unwrap();
+        set_role_admins(1, vec![0]);
+        set_mock_instruction_counter(0);
+        let result = grant_roles_batch(vec![
+            (user1, vec![(0, None), (1, None)]),
+            (user2, vec![(1, None)]),
+        ]);
+        assert_eq!(result.results, vec![vec![true, true], vec![true]]);
+        assert_eq!(result.next, None);
+        assert_eq!(get_role_member_count(1), 2);
+
+        set_mock_caller(user1);
+        let result = revoke_roles_batch(vec![(user2, vec![0, 1]), (user3, vec![1])]);
+        assert_eq!(result.results, vec![vec![false, true], vec![true]]);
+        assert!(!user_has_role(1, user2));
+
+        set_mock_instruction_counter(BATCH_INSTRUCTION_LIMIT + 1);
+        let result = grant_roles_batch(vec![(user2, vec![(1, None)]), (user3, vec![(1, None)])]);
+        assert!(result.results.is_empty());
+        assert_eq!(result.next, Some(0));
+        assert!(!user_has_role(1, user2));
+    }
+
+    fn admin_or_minter() -> Guard {
+        any_of([Guard::admin(), Guard::role_named("minter")])
+    }
+
+    #[test]
+    #[should_panic(expected = "msg_reject should only be called inside canisters")]
+    #[modifiers("guard@admin_or_minter()")]
+    fn test_syntax_guard_modifiers() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+    }
+
+    #[test]
+    fn test_guard_roles() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        assert!(admin_or_minter().check().is_err());
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        define_role(1, "minter".to_string(), String::new());
+        grant_roles(
+            vec![(1, None), (2, None)],
+            Principal::from_text(MOCK_USER_1).unwrap(),
+        );
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        assert!(admin_or_minter().check().is_ok());
+        assert!(any_of([Guard::role(3), Guard::roles_all(vec![1, 2])])
+            .check()
+            .is_ok());
+        assert_eq!(
+            all_of([Guard::roles_any(vec![2]), Guard::role(3)]).check(),
+            Err(RusticError::MissingRole(3))
+        );
+    }
+
+    #[test]
+    fn test_role_delegation() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        let admin = canister_caller();
+        let user1 = Principal::from_text(MOCK_USER_1).unwrap();
+        let user2 = Principal::from_text(MOCK_USER_2).unwrap();
+        set_mock_time(1_000);
+        grant_roles(vec![(3, None)], user1);
+
+        set_mock_caller(user1);
+        assert_eq!(
+            try_delegate_roles(vec![3, 4], user2, 2_000),
+            Err(RusticError::MissingRole(4))
+        );
+        assert_eq!(
+            try_delegate_roles(vec![3], user1, 2_000),
+            Err(RusticError::SelfDelegation)
+        );
+        assert_eq!(
+            try_delegate_roles(vec![3], user2, 1_001 + MAX_DELEGATION_DURATION),
+            Err(RusticError::InvalidExpiry)
+        );
+        delegate_roles(vec![3], user2, 2_000);
+        let delegation = RoleDelegation {
+            delegator: user1,
+            delegate: user2,
+            roles: vec![3],
+            expires_at: 2_000,
+        };
+        assert_eq!(get_delegations_by(user1), vec![delegation.clone()]);
+        assert_eq!(get_delegations_to(user2), vec![delegation]);
+
+        set_mock_caller(user2);
+        assert!(has_role(3).is_ok());
+        assert!(user_has_roles_all(vec![3], user2));
+        assert!(get_user_roles(user2).is_empty());
+        assert_eq!(
+            try_delegate_roles(vec![3], admin, 2_000),
+            Err(RusticError::MissingRole(3))
+        );
+        // a delegated role-admin role does not allow granting or revoking
+        set_mock_caller(admin);
+        set_role_admins(4, vec![3]);
+        set_mock_caller(user1);
+        assert_eq!(grant_roles(vec![(4, None)], user1), vec![true]);
+        set_mock_caller(user2);
+        assert_eq!(grant_roles(vec![(4, None)], user2), vec![false]);
+        assert_eq!(revoke_roles(vec![4], user1), vec![false]);
+        assert_eq!(
+            grant_roles_batch(vec![(user2, vec![(4, None)])]).results,
+            vec![vec![false]]
+        );
+        assert!(!user_has_role(4, user2));
+        assert!(user_has_role(4, user1));
+
+        // the delegation only holds while the delegator has the role
+        set_mock_caller(admin);
+        revoke_roles(vec![3], user1);
+        assert!(!user_has_role(3, user2));
+        grant_roles(vec![(3, None)], user1);
+        assert!(user_has_role(3, user2));
+
+        set_mock_time(2_000);
+        assert!(!user_has_role(3, user2));
+        assert!(get_delegations_to(user2).is_empty());
+
+        set_mock_time(1_500);
+        set_mock_caller(user1);
+        revoke_delegation(user2);
+        assert!(!user_has_role(3, user2));
+        assert!(get_delegations_by(user1).is_empty());
+    }
+
+    #[test]
+    fn test_role_expiry() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        let user1 = Principal::from_text(MOCK_USER_1).unwrap();
+        set_mock_time(1_000);
+        grant_roles(
+            vec![
+                (Role::R0.into(), Some(2_000)),
+                (Role::R1.into(), Some(2_000)),
+                (Role::R2.into(), None),
+                (4, Some(2_500)),
+            ],
+            user1,
+        );
+        // the expiry queue is rebuilt after upgrading from a version without it
+        ROLE_EXPIRY_QUEUE.with(|q| q.borrow_mut().clear_new());
+        role_expiry_queue_init();
+        assert_eq!(ROLE_EXPIRY_QUEUE.with(|q| q.borrow().len()), 3);
+        assert_eq!(get_role_expiry(Role::R0.into(), user1), Some(2_000));
+        assert_eq!(get_role_expiry(Role::R2.into(), user1), None);
+        assert!(user_has_roles_all(vec![0, 1, 2], user1));
+
+        // granting again replaces the expiry
+        grant_roles(vec![(Role::R1.into(), None)], user1);
+        set_mock_time(2_000);
+        assert!(!user_has_role(Role::R0.into(), user1));
+        assert!(user_has_roles_all(vec![1, 2], user1));
+        assert!(!user_has_roles_any(vec![0, 3], user1));
+        assert_eq!(get_user_roles(user1), vec![1, 2, 4]);
+        set_mock_caller(user1);
+        assert!(has_role(Role::R0.into()).is_err());
+        assert!(has_roles_all(vec![0, 1]).is_err());
+        assert!(has_roles_any(vec![0, 1]).is_ok());
+
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        // expired grants are excluded before they are pruned
+        assert_eq!(get_role_member_count(Role::R0.into()), 0);
+        assert!(get_role_members(Role::R0.into(), None, 10)
+            .members
+            .is_empty());
+        assert_eq!(get_role_member_count(4), 1);
+        assert_eq!(prune_expired_roles(10), 1);
+        assert_eq!(prune_expired_roles(10), 0);
+        assert_eq!(get_role_member_count(Role::R0.into()), 0);
+        assert_eq!(get_role_expiry(4, user1), Some(2_500));
+        set_mock_time(2_500);
+        assert_eq!(get_role_member_count(4), 0);
+        assert_eq!(prune_expired_roles(10), 1);
+        assert!(!user_has_role(4, user1));
+        assert_eq!(get_role_expiry(Role::R0.into(), user1), None);
+
+        // an expired grant can be renewed
+        grant_roles(vec![(Role::R1.into(), Some(3_000))], user1);
+        assert!(user_has_role(Role::R1.into(), user1));
+        revoke_roles(vec![Role::R1.into()], user1);
+        assert_eq!(get_role_expiry(Role::R1.into(), user1), None);
+    }
+
+    #[test]
+    fn test_role_members() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        let users =
+            [MOCK_USER_1, MOCK_USER_2, MOCK_USER_3].map(|u| Principal::from_text(u).unwrap());
+        for user in users {
+            grant_roles(vec![(Role::R0.into(), None)], user);
+        }
+        grant_roles(
+            vec![(Role::R0.into(), None), (Role::R1.into(), None)],
+            users[0],
+        );
+        assert_eq!(get_role_member_count(Role::R0.into()), 3);
+        assert_eq!(get_role_member_count(Role::R1.into()), 1);
+        assert_eq!(get_role_member_count(Role::R2.into()), 0);
+
+        let page = get_role_members(Role::R0.into(), None, 2);
+        assert_eq!(page.members.len(), 2);
+        let next = get_role_members(Role::R0.into(), page.next, 2);
+        assert_eq!(next.members.len(), 1);
+        assert_eq!(next.next, None);
+        let mut all = [page.members, next.members].concat();
+        all.sort();
+        let mut expected = users.to_vec();
+        expected.sort();
+        assert_eq!(all, expected);
+
+        revoke_roles(vec![Role::R0.into(), Role::R1.into()], users[0]);
+        revoke_roles(
+            vec![Role::R0.into()],
+            Principal::from_text(MOCK_CANISTER_0).unwrap(),
+        );
+        assert_eq!(get_role_member_count(Role::R0.into()), 2);
+        assert_eq!(get_role_member_count(Role::R1.into()), 0);
+        assert!(get_role_members(Role::R1.into(), None, 10)
+            .members
+            .is_empty());
+        assert!(!get_role_members(Role::R0.into(), None, 10)
+            .members
+            .contains(&users[0]));
+    }
 }
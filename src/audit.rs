@@ -0,0 +1,537 @@
+#![cfg(feature = "audit-events")]
+
+//! Append-only audit event log.
+//!
+//! Privileged operations of the `access`, `access-roles`, `pausable`, `multisig` and `lifecycle` features
+//! append an [`AuditEvent`] to a log in stable memory, which is persisted across canister upgrades.
+//! Applications can append their own events with [`audit_event`].
+//!
+//! Events are never modified or removed. The index of an event is its position in the log.
+//! The log can be read in pages with [`get_audit_events`].
+//!
+//! # Hash chain
+//! Each event carries the [`AuditEvent::hash`] of the previous event (all zeros for the first event),
+//! so history cannot be rewritten without changing the hash of the last event, the head hash.
+//! The head hash is set as the certified data of the canister after every event.
+//! [`get_audit_certificate`] returns the head hash together with the certificate,
+//! and [`verify_audit_events`] checks a downloaded range of events against a head hash.
+//! The certificate itself must be validated by the client, e.g. with `ic-agent`,
+//! and the head hash is the `certified_data` of the canister in the certificate.
+//!
+//! The certified data holds a single hash per canister,
+//! so applications setting their own certified data cannot use the certified head hash.
+//!
+//! # Examples
+//! ```
+//! use rustic::audit::audit_event;
+//! pub fn set_fee(fee: u64) {
+//!     // update the fee
+//!     audit_event(format!("set_fee {fee}"), None);
+//! }
+//! ```
+
+use crate::memory_map::*;
+#[cfg(test)]
+use crate::testing::*;
+use crate::types::*;
+use crate::utils::*;
+use candid::{CandidType, Principal};
+use ic_cdk_macros::query;
+use ic_stable_structures::Log;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+/// Maximum number of events returned in a single page.
+pub const MAX_AUDIT_PAGE_SIZE: u64 = 1000;
+
+/// SHA-256 hash of an audit event.
+pub type AuditHash = [u8; 32];
+
+/// A privileged operation recorded in the audit log.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    OwnershipTransferStarted,
+    OwnershipTransferCancelled,
+    OwnershipTransferred,
+    OwnershipRenounced,
+    OwnershipTransferTimeoutSet(u64),
+    AdminGranted,
+    AdminRevoked,
+    AdminRenounced,
+    RolesGranted(Vec<u8>),
+    RolesRevoked(Vec<u8>),
+    RolesGrantedUntil {
+        roles: Vec<u8>,
+        expires_at: u64,
+    },
+    /// Expired role grants removed by the cleanup routine.
+    RolesExpired(Vec<u8>),
+    RoleAdminsSet {
+        role: u8,
+        admins: Vec<u8>,
+    },
+    RoleAdminsRevoked {
+        role: u8,
+        admins: Vec<u8>,
+    },
+    RoleDefined {
+        role: u8,
+        name: String,
+    },
+    RoleUndefined(u8),
+    /// Roles delegated by the actor to the target.
+    RolesDelegated {
+        roles: Vec<u8>,
+        expires_at: u64,
+    },
+    /// Delegation of the actor to the target revoked.
+    DelegationRevoked,
+    Paused,
+    Resumed,
+    /// Permission of the method set in the permission table.
+    PermissionSet(String),
+    /// Permission of the method removed from the permission table.
+    PermissionRemoved(String),
+    /// Target added to the denylist.
+    Denylisted,
+    /// Target removed from the denylist.
+    DenylistRemoved,
+    /// Target added to the allowlist.
+    Allowlisted,
+    /// Target removed from the allowlist.
+    AllowlistRemoved,
+    AllowlistOnlySet(bool),
+    ProposalCreated(u64),
+    ProposalApproved(u64),
+    ProposalCancelled(u64),
+    /// Code upgrade, with the canister version text after the upgrade.
+    Upgraded(String),
+    /// Application defined event.
+    Custom(String),
+}
+
+/// An entry of the audit log.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub index: u64,
+    pub timestamp: u64,
+    /// The IC canister version at the time of the event.
+    pub canister_version: u64,
+    /// The caller performing the action.
+    pub actor: Principal,
+    pub action: AuditAction,
+    /// The principal affected by the action, if any.
+    pub target: Option<Principal>,
+    /// Hash of the previous event, or all zeros for the first event.
+    pub prev_hash: AuditHash,
+}
+
+impl AuditEvent {
+    /// Returns the SHA-256 hash of the event, including the hash of the previous event.
+    ///
+    /// The hash covers a fixed encoding of the event, see [`AuditAction::tag`],
+    /// so adding variants to [`AuditAction`] does not change the hash of existing events.
+    pub fn hash(&self) -> AuditHash {
+        let mut hasher = Sha256::new();
+        hasher.update(b"rustic-audit-event");
+        hasher.update(self.index.to_be_bytes());
+        hasher.update(self.timestamp.to_be_bytes());
+        hasher.update(self.canister_version.to_be_bytes());
+        hash_principal(&mut hasher, Some(&self.actor));
+        hash_action(&mut hasher, &self.action);
+        hash_principal(&mut hasher, self.target.as_ref());
+        hasher.update(self.prev_hash);
+        hasher.finalize().into()
+    }
+}
+
+impl AuditAction {
+    /// Returns the tag of the variant in the hash encoding.
+    /// Tags are never changed or reused, new variants get a new tag.
+    pub fn tag(&self) -> u8 {
+        match self {
+            Self::OwnershipTransferStarted => 0,
+            Self::OwnershipTransferCancelled => 1,
+            Self::OwnershipTransferred => 2,
+            Self::OwnershipRenounced => 3,
+            Self::OwnershipTransferTimeoutSet(_) => 4,
+            Self::AdminGranted => 5,
+            Self::AdminRevoked => 6,
+            Self::AdminRenounced => 7,
+            Self::RolesGranted(_) => 8,
+            Self::RolesRevoked(_) => 9,
+            Self::RolesGrantedUntil { .. } => 10,
+            Self::RolesExpired(_) => 11,
+            Self::RoleAdminsSet { .. } => 12,
+            Self::RoleAdminsRevoked { .. } => 13,
+            Self::RoleDefined { .. } => 14,
+            Self::RoleUndefined(_) => 15,
+            Self::RolesDelegated { .. } => 16,
+            Self::DelegationRevoked => 17,
+            Self::Paused => 18,
+            Self::Resumed => 19,
+            Self::PermissionSet(_) => 20,
+            Self::PermissionRemoved(_) => 21,
+            Self::Denylisted => 22,
+            Self::DenylistRemoved => 23,
+            Self::Allowlisted => 24,
+            Self::AllowlistRemoved => 25,
+            Self::AllowlistOnlySet(_) => 26,
+            Self::ProposalCreated(_) => 27,
+            Self::ProposalApproved(_) => 28,
+            Self::ProposalCancelled(_) => 29,
+            Self::Upgraded(_) => 30,
+            Self::Custom(_) => 31,
+        }
+    }
+}
+
+// The tag of the variant followed by its fields in declaration order.
+// Integers are big endian, and variable length fields are length-prefixed.
+fn hash_action(hasher: &mut Sha256, action: &AuditAction) {
+    hasher.update([action.tag()]);
+    match action {
+        AuditAction::OwnershipTransferStarted
+        | AuditAction::OwnershipTransferCancelled
+        | AuditAction::OwnershipTransferred
+        | AuditAction::OwnershipRenounced
+        | AuditAction::AdminGranted
+        | AuditAction::AdminRevoked
+        | AuditAction::AdminRenounced
+        | AuditAction::DelegationRevoked
+        | AuditAction::Paused
+        | AuditAction::Resumed
+        | AuditAction::Denylisted
+        | AuditAction::DenylistRemoved
+        | AuditAction::Allowlisted
+        | AuditAction::AllowlistRemoved => {}
+        AuditAction::OwnershipTransferTimeoutSet(x)
+        | AuditAction::ProposalCreated(x)
+        | AuditAction::ProposalApproved(x)
+        | AuditAction::ProposalCancelled(x) => hasher.update(x.to_be_bytes()),
+        AuditAction::RolesGranted(roles)
+        | AuditAction::RolesRevoked(roles)
+        | AuditAction::RolesExpired(roles) => hash_bytes(hasher, roles),
+        AuditAction::RolesGrantedUntil { roles, expires_at }
+        | AuditAction::RolesDelegated { roles, expires_at } => {
+            hash_bytes(hasher, roles);
+            hasher.update(expires_at.to_be_bytes());
+        }
+        AuditAction::RoleAdminsSet { role, admins }
+        | AuditAction::RoleAdminsRevoked { role, admins } => {
+            hasher.update([*role]);
+            hash_bytes(hasher, admins);
+        }
+        AuditAction::RoleDefined { role, name } => {
+            hasher.update([*role]);
+            hash_bytes(hasher, name.as_bytes());
+        }
+        AuditAction::RoleUndefined(role) => hasher.update([*role]),
+        AuditAction::AllowlistOnlySet(enabled) => hasher.update([*enabled as u8]),
+        AuditAction::PermissionSet(text)
+        | AuditAction::PermissionRemoved(text)
+        | AuditAction::Upgraded(text)
+        | AuditAction::Custom(text) => hash_bytes(hasher, text.as_bytes()),
+    }
+}
+
+fn hash_bytes(hasher: &mut Sha256, bytes: &[u8]) {
+    hasher.update((bytes.len() as u64).to_be_bytes());
+    hasher.update(bytes);
+}
+
+// Length-prefixed, so that adjacent fields cannot be confused.
+fn hash_principal(hasher: &mut Sha256, principal: Option<&Principal>) {
+    match principal {
+        Some(p) => {
+            hasher.update([1, p.as_slice().len() as u8]);
+            hasher.update(p.as_slice());
+        }
+        None => hasher.update([0]),
+    }
+}
+
+/// A page of audit events.
+/// `next` is the index to pass as `start` for the following page, or `None` if there are no more events.
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct AuditPage {
+    pub events: Vec<AuditEvent>,
+    pub next: Option<u64>,
+}
+
+/// The head of the audit log with its certificate.
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct AuditCertificate {
+    /// Number of events in the log.
+    pub len: u64,
+    /// Hash of the last event, or all zeros if the log is empty.
+    pub head: AuditHash,
+    /// Certificate of the certified data, which is set to `head`.
+    pub certificate: Option<ByteBuf>,
+}
+
+thread_local! {
+    static AUDIT_LOG: RefCell<Log<Cbor<AuditEvent>, VM, VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            #[allow(clippy::expect_used)] // safe unwrap during init
+            RefCell::new(Log::init(
+                mm.borrow().get(AUDIT_LOG_IDX_ID),
+                mm.borrow().get(AUDIT_LOG_MEM_ID),
+            ).expect("Failed to initialize the audit log"))
+    });
+}
+
+fn head_hash(log: &Log<Cbor<AuditEvent>, VM, VM>) -> AuditHash {
+    log.len()
+        .checked_sub(1)
+        .and_then(|i| log.get(i))
+        .map_or([0; 32], |e| e.hash())
+}
+
+// Appends an event performed by the caller, and certifies the new head hash.
+pub(crate) fn emit(action: AuditAction, target: Option<Principal>) {
+    AUDIT_LOG.with(|l| {
+        let l = l.borrow();
+        let event = AuditEvent {
+            index: l.len(),
+            timestamp: canister_time(),
+            canister_version: canister_version(),
+            actor: canister_caller(),
+            action,
+            target,
+            prev_hash: head_hash(&l),
+        };
+        let head = event.hash();
+        #[allow(clippy::expect_used)] // unwrap desired
+        l.append(&Cbor(event)).expect("Audit log append failed");
+        canister_set_certified_data(&head);
+    });
+}
+
+// Sets the certified data to the head hash.
+// The certified data is not guaranteed to survive upgrades, so this is called in the post-upgrade hook.
+pub(crate) fn audit_certify() {
+    canister_set_certified_data(&audit_head_hash());
+}
+
+/// Returns the hash of the last event, or all zeros if the log is empty.
+pub fn audit_head_hash() -> AuditHash {
+    AUDIT_LOG.with(|l| head_hash(&l.borrow()))
+}
+
+/// Appends an application defined event to the audit log, performed by the caller.
+///
+/// # Panics
+/// Panics if the stable memory is exhausted, so the audited operation is reverted.
+pub fn audit_event(action: String, target: Option<Principal>) {
+    emit(AuditAction::Custom(action), target);
+}
+
+/// Returns the number of events in the audit log.
+#[query]
+pub fn get_audit_len() -> u64 {
+    AUDIT_LOG.with(|l| l.borrow().len())
+}
+
+/// Returns a page of at most `limit` audit events starting from index `start`.
+#[query]
+pub fn get_audit_events(start: u64, limit: u64) -> AuditPage {
+    AUDIT_LOG.with(|l| {
+        let l = l.borrow();
+        let end = start
+            .saturating_add(limit.min(MAX_AUDIT_PAGE_SIZE))
+            .min(l.len());
+        AuditPage {
+            events: (start..end).filter_map(|i| l.get(i).map(|e| e.0)).collect(),
+            next: (end < l.len()).then_some(end),
+        }
+    })
+}
+
+/// Returns the head hash of the audit log and its certificate.
+#[query]
+pub fn get_audit_certificate() -> AuditCertificate {
+    AUDIT_LOG.with(|l| {
+        let l = l.borrow();
+        AuditCertificate {
+            len: l.len(),
+            head: head_hash(&l),
+            certificate: canister_data_certificate().map(ByteBuf::from),
+        }
+    })
+}
+
+/// Verifies that a range of consecutive events is part of the chain ending at the head hash `head`.
+/// The range must contain the last event of the log. A range starting from index 0 covers the whole history.
+///
+/// This function does not make any system calls and can be used off-chain.
+///
+/// # Examples
+/// ```
+/// # use rustic::audit::*;
+/// # fn download() -> (Vec<AuditEvent>, AuditHash) { (vec![], [0; 32]) }
+/// let (events, certified_head) = download();
+/// assert!(verify_audit_events(&events, &certified_head).is_ok());
+/// ```
+pub fn verify_audit_events(events: &[AuditEvent], head: &AuditHash) -> Result<(), String> {
+    let Some(first) = events.first() else {
+        return (*head == [0; 32])
+            .then_some(())
+            .ok_or("No events to verify against a non-empty head".to_string());
+    };
+    if first.index == 0 && first.prev_hash != [0; 32] {
+        return Err("The first event must not have a previous hash".to_string());
+    }
+    let mut hash = first.hash();
+    for (prev, event) in events.iter().zip(events.iter().skip(1)) {
+        if event.index != prev.index + 1 {
+            return Err(format!(
+                "Event {} does not follow event {}",
+                event.index, prev.index
+            ));
+        }
+        if event.prev_hash != hash {
+            return Err(format!(
+                "Event {} does not chain to the previous event",
+                event.index
+            ));
+        }
+        hash = event.hash();
+    }
+    (hash == *head)
+        .then_some(())
+        .ok_or("The last event does not match the head hash".to_string())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::access_control::*;
+
+    #[test]
+    fn test_audit_events() {
+        let user0 = Principal::from_text(MOCK_USER_0).unwrap();
+        let user1 = Principal::from_text(MOCK_USER_1).unwrap();
+        set_mock_caller(user0);
+        access_init(canister_caller());
+        set_mock_time(42);
+        set_mock_version(3);
+
+        grant_admin(user1);
+        transfer_ownership(Some(user1));
+        set_mock_caller(user1);
+        accept_ownership();
+        audit_event("custom".to_string(), None);
+        assert_eq!(get_audit_len(), 4);
+
+        let page = get_audit_events(0, 2);
+        assert_eq!(
+            page.events[0],
+            AuditEvent {
+                index: 0,
+                timestamp: 42,
+                canister_version: 3,
+                actor: user0,
+                action: AuditAction::AdminGranted,
+                target: Some(user1),
+                prev_hash: [0; 32],
+            }
+        );
+        assert_eq!(page.events[1].action, AuditAction::OwnershipTransferStarted);
+        assert_eq!(page.next, Some(2));
+
+        let page = get_audit_events(2, 10);
+        assert_eq!(page.events.len(), 2);
+        assert_eq!(page.events[0].index, 2);
+        assert_eq!(page.events[0].actor, user1);
+        assert_eq!(page.events[0].action, AuditAction::OwnershipTransferred);
+        assert_eq!(
+            page.events[1].action,
+            AuditAction::Custom("custom".to_string())
+        );
+        assert_eq!(page.next, None);
+
+        assert!(get_audit_events(10, 10).events.is_empty());
+    }
+
+    #[test]
+    fn test_audit_hash_chain() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        assert_eq!(audit_head_hash(), [0; 32]);
+        assert!(verify_audit_events(&[], &[0; 32]).is_ok());
+
+        for i in 0..5 {
+            audit_event(format!("event {i}"), None);
+        }
+        set_mock_data_certificate(Some(vec![1, 2, 3]));
+        let certificate = get_audit_certificate();
+        assert_eq!(certificate.len, 5);
+        assert_eq!(mock_certified_data(), certificate.head.to_vec());
+        assert_eq!(certificate.certificate.unwrap().to_vec(), vec![1, 2, 3]);
+
+        let events = get_audit_events(0, 10).events;
+        assert_eq!(events[1].prev_hash, events[0].hash());
+        assert!(verify_audit_events(&events, &certificate.head).is_ok());
+        assert!(verify_audit_events(&events[2..], &certificate.head).is_ok());
+        assert!(verify_audit_events(&events[..4], &certificate.head).is_err());
+        assert!(verify_audit_events(&[], &certificate.head).is_err());
+
+        let mut tampered = events.clone();
+        tampered[1].action = AuditAction::Custom("rewritten".to_string());
+        assert!(verify_audit_events(&tampered, &certificate.head).is_err());
+
+        let mut gap = events.clone();
+        gap.remove(2);
+        assert!(verify_audit_events(&gap, &certificate.head).is_err());
+    }
+
+    // Pins the hash encoding, which must not change as long as audit logs using it exist.
+    #[test]
+    fn test_audit_hash_stable() {
+        let event = AuditEvent {
+            index: 7,
+            timestamp: 42,
+            canister_version: 3,
+            actor: Principal::from_text(MOCK_USER_0).unwrap(),
+            action: AuditAction::RolesGrantedUntil {
+                roles: vec![1, 2],
+                expires_at: 100,
+            },
+            target: Some(Principal::from_text(MOCK_USER_1).unwrap()),
+            prev_hash: [1; 32],
+        };
+        assert_eq!(
+            event.hash(),
+            [
+                173, 134, 253, 175, 199, 53, 24, 36, 202, 163, 248, 159, 207, 209, 62, 132, 143,
+                53, 95, 188, 179, 139, 224, 254, 157, 70, 6, 188, 98, 124, 150, 154
+            ]
+        );
+    }
+
+    #[cfg(feature = "access-roles")]
+    #[test]
+    fn test_audit_roles() {
+        let user1 = Principal::from_text(MOCK_USER_1).unwrap();
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+
+        set_mock_caller(user1);
+        grant_roles(vec![(2, None)], user1);
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        grant_roles(vec![(0, None), (1, None)], user1);
+        set_role_admins(2, vec![0]);
+        let events = get_audit_events(0, 10).events;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].action, AuditAction::RolesGranted(vec![0, 1]));
+        assert_eq!(
+            events[1].action,
+            AuditAction::RoleAdminsSet {
+                role: 2,
+                admins: vec![0]
+            }
+        );
+    }
+}
@@ -0,0 +1,377 @@
+#![cfg(feature = "caller-lists")]
+
+//! Admin managed denylist and allowlist of callers.
+//!
+//! Methods are protected with the [`allowed_caller`] guard, which rejects principals on the denylist.
+//! In allowlist-only mode, e.g. for a closed beta, it additionally rejects principals not on the allowlist.
+//! The denylist takes precedence over the allowlist, and applies to admins as well.
+//! Admin methods guarded by [`only_admin`] are not affected, so admins cannot lock themselves out.
+//!
+//! # Examples
+//! ```rust
+//! # use ic_cdk::update;
+//! # use rustic::caller_lists::allowed_caller;
+//! # use rustic_macros::modifiers;
+//! #[update]
+//! #[modifiers("allowed_caller")]
+//! fn transfer() {}
+//! ```
+//!
+//! Both lists are kept in stable maps, so they survive upgrades. Only admins can list them.
+//! With the `audit-events` feature, changes of the lists are recorded in the audit log.
+
+use crate::access_control::*;
+#[cfg(feature = "audit-events")]
+use crate::audit::*;
+use crate::error::*;
+use crate::global_flags::*;
+use crate::memory_map::*;
+#[cfg(test)]
+use crate::testing::*;
+use crate::types::*;
+use crate::utils::*;
+use candid::{CandidType, Principal};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use rustic_macros::modifiers;
+use std::cell::RefCell;
+use std::thread::LocalKey;
+
+/// Maximum number of principals returned in a single page.
+pub const MAX_CALLER_LIST_PAGE_SIZE: u64 = 1000;
+/// Maximum number of principals added or removed in a single call.
+pub const MAX_CALLER_LIST_BATCH_SIZE: u64 = 1000;
+
+type CallerList = RefCell<StableBTreeMap<StablePrincipal, (), VM>>;
+
+thread_local! {
+    // can be lazily initialized
+    static DENYLIST: CallerList =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(DENYLIST_MEM_ID)))
+    });
+
+    // can be lazily initialized
+    static ALLOWLIST: CallerList =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(ALLOWLIST_MEM_ID)))
+    });
+}
+
+/// A page of a caller list.
+/// `next` is the principal to pass as `start` for the following page, or `None` if there are no more principals.
+#[derive(Clone, CandidType, serde::Serialize, serde::Deserialize)]
+pub struct CallerListPage {
+    pub principals: Vec<Principal>,
+    pub next: Option<Principal>,
+}
+
+fn contains(list: &'static LocalKey<CallerList>, principal: Principal) -> bool {
+    list.with(|l| l.borrow().contains_key(&principal.into()))
+}
+
+fn page(
+    list: &'static LocalKey<CallerList>,
+    start: Option<Principal>,
+    limit: u64,
+) -> CallerListPage {
+    let start = start.unwrap_or(Principal::management_canister());
+    let limit = limit.min(MAX_CALLER_LIST_PAGE_SIZE) as usize;
+    list.with(|l| {
+        let mut principals: Vec<Principal> = l
+            .borrow()
+            .range(StablePrincipal::from(start)..)
+            .take(limit + 1)
+            .map(|(p, _)| (&p).into())
+            .collect();
+        let next = (principals.len() > limit)
+            .then(|| principals.pop())
+            .flatten();
+        CallerListPage { principals, next }
+    })
+}
+
+// Returns the principals whose membership changed.
+fn update_list(
+    list: &'static LocalKey<CallerList>,
+    principals: Vec<Principal>,
+    add: bool,
+) -> Result<Vec<Principal>, RusticError> {
+    check_admin()?;
+    if principals.len() as u64 > MAX_CALLER_LIST_BATCH_SIZE {
+        return Err(RusticError::BatchTooLarge(MAX_CALLER_LIST_BATCH_SIZE));
+    }
+    Ok(list.with(|l| {
+        let mut l = l.borrow_mut();
+        principals
+            .into_iter()
+            .filter(|p| {
+                if add {
+                    l.insert(p.into(), ()).is_none()
+                } else {
+                    l.remove(&p.into()).is_some()
+                }
+            })
+            .collect()
+    }))
+}
+
+/// Checks if a principal is on the denylist.
+/// Anyone can check a single principal, while listing the principals is restricted to admins.
+#[query]
+pub fn is_denylisted(principal: Principal) -> bool {
+    contains(&DENYLIST, principal)
+}
+
+/// Checks if a principal is on the allowlist.
+#[query]
+pub fn is_allowlisted(principal: Principal) -> bool {
+    contains(&ALLOWLIST, principal)
+}
+
+/// Query method to get whether only principals on the allowlist are allowed.
+#[query]
+pub fn is_allowlist_only() -> bool {
+    #[allow(clippy::unwrap_used)] // unwrap desired
+    GLOBAL_FLAGS.with(|f| f.borrow().get().0.clone().unwrap().allowlist_only)
+}
+
+/// Checks whether a principal passes the [`allowed_caller`] guard.
+#[query]
+pub fn user_is_allowed(principal: Principal) -> bool {
+    check_allowed(principal).is_ok()
+}
+
+/// Returns a page of at most `limit` denylisted principals, in ascending order starting from `start`.
+/// Must be called by admins.
+#[query]
+#[modifiers("only_admin")]
+pub fn get_denylist(start: Option<Principal>, limit: u64) -> CallerListPage {
+    page(&DENYLIST, start, limit)
+}
+
+/// Returns a page of at most `limit` allowlisted principals, in ascending order starting from `start`.
+/// Must be called by admins.
+#[query]
+#[modifiers("only_admin")]
+pub fn get_allowlist(start: Option<Principal>, limit: u64) -> CallerListPage {
+    page(&ALLOWLIST, start, limit)
+}
+
+fn check_allowed(principal: Principal) -> Result<(), RusticError> {
+    if is_denylisted(principal) {
+        Err(RusticError::CallerDenylisted)
+    } else if is_allowlist_only() && !is_allowlisted(principal) {
+        Err(RusticError::CallerNotAllowlisted)
+    } else {
+        Ok(())
+    }
+}
+
+/// Guard method for rejecting denylisted callers, and callers not on the allowlist in allowlist-only mode.
+/// This is typically used in conjunction with the [`modifiers`] macro.
+pub fn allowed_caller() -> Result<(), String> {
+    Ok(check_allowed_caller()?)
+}
+
+/// Checks that the caller passes the caller lists, returning a typed error.
+pub fn check_allowed_caller() -> Result<(), RusticError> {
+    check_allowed(canister_caller())
+}
+
+impl Guard {
+    /// Passes if the caller passes the caller lists, see [`allowed_caller`].
+    pub fn allowed_caller() -> Self {
+        Self::new("allowed caller", check_allowed_caller)
+    }
+}
+
+/// Adds principals to the denylist. Must be called by admins.
+/// At most [`MAX_CALLER_LIST_BATCH_SIZE`] principals can be added at once.
+#[update]
+#[modifiers("only_admin")]
+pub fn add_to_denylist(principals: Vec<Principal>) {
+    unwrap_or_reject(try_add_to_denylist(principals))
+}
+
+/// Same as [`add_to_denylist`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_add_to_denylist(principals: Vec<Principal>) -> Result<(), RusticError> {
+    let _changed = update_list(&DENYLIST, principals, true)?;
+    #[cfg(feature = "audit-events")]
+    for p in _changed {
+        emit(AuditAction::Denylisted, Some(p));
+    }
+    Ok(())
+}
+
+/// Removes principals from the denylist. Must be called by admins.
+/// At most [`MAX_CALLER_LIST_BATCH_SIZE`] principals can be removed at once.
+#[update]
+#[modifiers("only_admin")]
+pub fn remove_from_denylist(principals: Vec<Principal>) {
+    unwrap_or_reject(try_remove_from_denylist(principals))
+}
+
+/// Same as [`remove_from_denylist`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_remove_from_denylist(principals: Vec<Principal>) -> Result<(), RusticError> {
+    let _changed = update_list(&DENYLIST, principals, false)?;
+    #[cfg(feature = "audit-events")]
+    for p in _changed {
+        emit(AuditAction::DenylistRemoved, Some(p));
+    }
+    Ok(())
+}
+
+/// Adds principals to the allowlist. Must be called by admins.
+/// At most [`MAX_CALLER_LIST_BATCH_SIZE`] principals can be added at once.
+#[update]
+#[modifiers("only_admin")]
+pub fn add_to_allowlist(principals: Vec<Principal>) {
+    unwrap_or_reject(try_add_to_allowlist(principals))
+}
+
+/// Same as [`add_to_allowlist`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_add_to_allowlist(principals: Vec<Principal>) -> Result<(), RusticError> {
+    let _changed = update_list(&ALLOWLIST, principals, true)?;
+    #[cfg(feature = "audit-events")]
+    for p in _changed {
+        emit(AuditAction::Allowlisted, Some(p));
+    }
+    Ok(())
+}
+
+/// Removes principals from the allowlist. Must be called by admins.
+/// At most [`MAX_CALLER_LIST_BATCH_SIZE`] principals can be removed at once.
+#[update]
+#[modifiers("only_admin")]
+pub fn remove_from_allowlist(principals: Vec<Principal>) {
+    unwrap_or_reject(try_remove_from_allowlist(principals))
+}
+
+/// Same as [`remove_from_allowlist`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_remove_from_allowlist(principals: Vec<Principal>) -> Result<(), RusticError> {
+    let _changed = update_list(&ALLOWLIST, principals, false)?;
+    #[cfg(feature = "audit-events")]
+    for p in _changed {
+        emit(AuditAction::AllowlistRemoved, Some(p));
+    }
+    Ok(())
+}
+
+/// Enables or disables allowlist-only mode. Must be called by admins.
+#[update]
+#[modifiers("only_admin")]
+pub fn set_allowlist_only(enabled: bool) {
+    unwrap_or_reject(try_set_allowlist_only(enabled))
+}
+
+/// Same as [`set_allowlist_only`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_set_allowlist_only(enabled: bool) -> Result<(), RusticError> {
+    check_admin()?;
+    GLOBAL_FLAGS.with(|f| {
+        let mut f = f.borrow_mut();
+        #[allow(clippy::unwrap_used)] // unwrap desired
+        let mut flags = f.get().0.clone().unwrap();
+        flags.allowlist_only = enabled;
+        #[allow(clippy::expect_used)] // unwrap desired
+        f.set(Cbor(Some(flags)))
+            .expect("Failed to set the allowlist-only flag");
+    });
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::AllowlistOnlySet(enabled), None);
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn test_caller_lists() {
+        let users = [MOCK_USER_0, MOCK_USER_1, MOCK_USER_2, MOCK_USER_3]
+            .map(|u| Principal::from_text(u).unwrap());
+        set_mock_caller(users[0]);
+        access_init(canister_caller());
+
+        add_to_denylist(vec![users[1]]);
+        add_to_allowlist(vec![users[1], users[2]]);
+        assert!(allowed_caller().is_ok());
+        set_mock_caller(users[1]);
+        assert_eq!(check_allowed_caller(), Err(RusticError::CallerDenylisted));
+        set_mock_caller(users[3]);
+        assert!(allowed_caller().is_ok());
+
+        set_mock_caller(users[0]);
+        set_allowlist_only(true);
+        assert!(is_allowlist_only());
+        assert!(user_is_allowed(users[2]));
+        assert!(!user_is_allowed(users[1]));
+        set_mock_caller(users[3]);
+        assert_eq!(
+            check_allowed_caller(),
+            Err(RusticError::CallerNotAllowlisted)
+        );
+        assert_eq!(
+            try_add_to_allowlist(vec![users[3]]),
+            Err(RusticError::NotAdmin)
+        );
+
+        set_mock_caller(users[0]);
+        remove_from_denylist(vec![users[1], users[3]]);
+        assert!(user_is_allowed(users[1]));
+        remove_from_allowlist(vec![users[1]]);
+        assert!(!user_is_allowed(users[1]));
+        set_allowlist_only(false);
+        assert!(user_is_allowed(users[3]));
+
+        assert_eq!(
+            try_add_to_denylist(vec![users[3]; MAX_CALLER_LIST_BATCH_SIZE as usize + 1]),
+            Err(RusticError::BatchTooLarge(MAX_CALLER_LIST_BATCH_SIZE))
+        );
+        assert!(!is_denylisted(users[3]));
+    }
+
+    #[test]
+    fn test_get_caller_list() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        let canisters = [MOCK_CANISTER_0, MOCK_CANISTER_1, MOCK_CANISTER_2]
+            .map(|c| Principal::from_text(c).unwrap());
+        add_to_denylist(canisters.to_vec());
+        let mut sorted = canisters.to_vec();
+        sorted.sort_by_key(|p| StablePrincipal::from(p));
+
+        let page = get_denylist(None, 2);
+        assert_eq!(page.principals, sorted[..2]);
+        assert_eq!(page.next, Some(sorted[2]));
+        let page = get_denylist(page.next, 2);
+        assert_eq!(page.principals, sorted[2..]);
+        assert_eq!(page.next, None);
+        assert!(get_allowlist(None, 10).principals.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "msg_reject should only be called inside canisters")]
+    fn test_add_to_denylist_unauth() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        add_to_denylist(vec![Principal::from_text(MOCK_USER_2).unwrap()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "msg_reject should only be called inside canisters")]
+    fn test_get_allowlist_unauth() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        get_allowlist(None, 10);
+    }
+}
@@ -0,0 +1,168 @@
+//! Typed errors of the Rustic guards and update methods.
+//!
+//! Guards used with the [`modifiers`](rustic_macros::modifiers) macro must return `Result<(), String>`,
+//! so they return the [`Display`](std::fmt::Display) text of a [`RusticError`].
+//! Each guard has a typed `check_` counterpart returning `Result<(), RusticError>`, e.g.
+//! [`check_owner`](crate::access_control::check_owner) for [`only_owner`](crate::access_control::only_owner).
+//!
+//! Update methods reject the call when they fail. Each of them has a `try_` variant, e.g.
+//! [`try_accept_ownership`](crate::access_control::try_accept_ownership), which returns the error
+//! to the client as a Candid variant instead, so clients do not need to match reject messages.
+//! A `try_` method that returns an error does not change any state.
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum RusticError {
+    /// The caller is not the owner.
+    NotOwner,
+    /// The caller is not an admin.
+    NotAdmin,
+    /// There is no pending ownership transfer.
+    NoPendingOwner,
+    /// The caller is not the pending owner.
+    NotPendingOwner,
+    /// The pending ownership transfer has expired.
+    OwnershipTransferExpired,
+    /// The change would leave the canister without an owner.
+    NoOwner,
+    /// The change would leave the canister without an admin.
+    LastAdmin,
+    /// The confirmation token does not match.
+    InvalidConfirmation,
+    /// The confirmation token has expired.
+    ConfirmationExpired,
+    /// The caller is anonymous.
+    AnonymousCaller,
+    /// The caller is not a user with a self-authenticating principal.
+    NotUserCaller,
+    /// The caller is not a canister.
+    NotCanisterCaller,
+    /// The caller is not a controller of the canister.
+    NotController,
+    /// The anonymous principal cannot be given the privilege.
+    AnonymousPrincipal,
+    /// The caller does not have the role.
+    MissingRole(u8),
+    /// The caller does not have the required roles.
+    MissingRoles(Vec<u8>),
+    /// No role is defined with the name.
+    RoleNotDefined(String),
+    /// The role name is empty.
+    EmptyRoleName,
+    /// The role name is already used by another role.
+    RoleNameTaken(String),
+    /// The delegation expiry is not in the future or exceeds the maximum delegation duration.
+    InvalidExpiry,
+    /// A principal cannot delegate to itself.
+    SelfDelegation,
+    /// The caller is not authorized to call the method by the permission table.
+    Unauthorized(String),
+    /// None of the alternatives of an `any_of` guard passed, with the error of each alternative.
+    NoneOf(Vec<RusticError>),
+    /// The guard negated by a `not` guard passed.
+    Negated(String),
+    /// The caller is on the denylist.
+    CallerDenylisted,
+    /// The caller is not on the allowlist in allowlist-only mode.
+    CallerNotAllowlisted,
+    /// The batch exceeds the maximum size.
+    BatchTooLarge(u64),
+    /// The canister is paused.
+    Paused,
+    /// The canister is not paused.
+    NotPaused,
+    /// The caller already has a call in progress through a reentrancy guard.
+    Reentrant,
+    /// The operation must be submitted as a multisig proposal.
+    MultisigEnabled,
+    /// The multisig threshold exceeds the number of admins, or would after the change.
+    ThresholdTooHigh,
+    /// The proposal does not exist.
+    ProposalNotFound,
+    /// The proposal has been executed or cancelled.
+    ProposalNotPending,
+    /// The proposal has expired.
+    ProposalExpired,
+    /// The caller is not the proposer.
+    NotProposer,
+}
+
+impl fmt::Display for RusticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotOwner => write!(f, "Caller is not the owner"),
+            Self::NotAdmin => write!(f, "Caller is not an admin"),
+            Self::NoPendingOwner => write!(f, "No pending owner"),
+            Self::NotPendingOwner => write!(f, "Only pending owner can accept ownership"),
+            Self::OwnershipTransferExpired => write!(f, "Pending ownership transfer has expired"),
+            Self::NoOwner => write!(
+                f,
+                "Cannot leave the canister without an owner, use renounce_ownership instead"
+            ),
+            Self::LastAdmin => write!(f, "Cannot remove the last admin"),
+            Self::InvalidConfirmation => write!(f, "Invalid confirmation token"),
+            Self::ConfirmationExpired => write!(f, "Confirmation token has expired"),
+            Self::AnonymousCaller => write!(f, "Anonymous caller is not allowed"),
+            Self::NotUserCaller => write!(f, "Caller is not a user"),
+            Self::NotCanisterCaller => write!(f, "Caller is not a canister"),
+            Self::NotController => write!(f, "Caller is not a controller"),
+            Self::AnonymousPrincipal => write!(f, "Anonymous principal is not allowed"),
+            Self::MissingRole(role) => write!(f, "Caller is missing role {role}"),
+            Self::MissingRoles(roles) => write!(f, "Caller is missing roles {roles:?}"),
+            Self::RoleNotDefined(name) => write!(f, "Role {name} is not defined"),
+            Self::EmptyRoleName => write!(f, "Role name must not be empty"),
+            Self::RoleNameTaken(name) => write!(f, "Role name is already defined: {name}"),
+            Self::InvalidExpiry => write!(
+                f,
+                "Expiry must be in the future and within the maximum delegation duration"
+            ),
+            Self::SelfDelegation => write!(f, "Cannot delegate to oneself"),
+            Self::Unauthorized(method) => write!(f, "Caller is not authorized to call {method}"),
+            Self::NoneOf(errors) => {
+                write!(f, "None of the guards passed: [")?;
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{e}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Negated(name) => write!(f, "Guard {name} must not pass"),
+            Self::CallerDenylisted => write!(f, "Caller is denylisted"),
+            Self::CallerNotAllowlisted => write!(f, "Caller is not allowlisted"),
+            Self::BatchTooLarge(max) => write!(f, "Batch exceeds the maximum size of {max}"),
+            Self::Paused => write!(f, "Contract is paused"),
+            Self::NotPaused => write!(f, "Contract is not paused"),
+            Self::Reentrant => write!(f, "ReentrancyGuard: reentrant call"),
+            Self::MultisigEnabled => {
+                write!(f, "Multisig is enabled, submit a proposal instead")
+            }
+            Self::ThresholdTooHigh => {
+                write!(f, "Threshold must not exceed the number of admins")
+            }
+            Self::ProposalNotFound => write!(f, "Proposal not found"),
+            Self::ProposalNotPending => write!(f, "Proposal is not pending"),
+            Self::ProposalExpired => write!(f, "Proposal has expired"),
+            Self::NotProposer => write!(f, "Only the proposer can cancel the proposal"),
+        }
+    }
+}
+
+impl std::error::Error for RusticError {}
+
+impl From<RusticError> for String {
+    fn from(e: RusticError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Returns the value of a `try_` method, or panics with the error, which rejects the call.
+#[cfg(feature = "access")]
+pub(crate) fn unwrap_or_reject<T>(result: Result<T, RusticError>) -> T {
+    // panic desired
+    result.unwrap_or_else(|e| panic!("{e}"))
+}
@@ -14,6 +14,10 @@ pub(crate) struct GlobalFlags {
     pub(crate) log_index: u64,
     pub(crate) trace_index: u64,
     pub(crate) user_page_end: u64,
+    #[serde(default)]
+    pub(crate) allowlist_only: bool,
+    #[serde(default)]
+    pub(crate) stable_log_dropped: u64,
 }
 
 thread_local! {
@@ -27,6 +31,8 @@ thread_local! {
                 log_index: 0,
                 trace_index: 0,
                 user_page_end: USER_PAGE_END,
+                allowlist_only: false,
+                stable_log_dropped: 0,
             })),
         ).expect("Failed to initialize the global flag cell")
     );
@@ -0,0 +1,419 @@
+#![cfg(feature = "https")]
+
+//! HTTP interface to the canister, served through the HTTP gateway.
+//!
+//! The `http_request` query method serves the following built-in routes:
+//! - `/metrics`: metrics in the Prometheus text format, including instruction histograms with the `inspection` feature.
+//! - `/status`: a short JSON status summary.
+//! - `/logs`: log entries as JSON (with the `logging` feature). Query parameters `start`, `limit` and `source` are supported,
+//!   where `source` is `log` (default), `trace` or `stable` (with the `stable-logging` feature).
+//!
+//! Requests through the HTTP gateway are anonymous, so `/logs` is not served unless enabled with [`set_logs_route_enabled`].
+//!
+//! Applications can serve their own routes with [`register_route`].
+//! Routes are kept in the heap, so they need to be registered again in the post-upgrade hook.
+//! A registered route takes precedence over a built-in route with the same path.
+
+#[cfg(feature = "pausable")]
+use crate::pausable::is_paused;
+#[cfg(test)]
+use crate::testing::*;
+use crate::utils::*;
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::query;
+use serde_bytes::ByteBuf;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: ByteBuf,
+}
+
+impl HttpRequest {
+    /// Returns the path of the url, without the query string.
+    pub fn path(&self) -> &str {
+        self.url.split('?').next().unwrap_or_default()
+    }
+
+    /// Returns the value of a query string parameter.
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.url.split_once('?').and_then(|(_, query)| {
+            query
+                .split('&')
+                .filter_map(|p| p.split_once('='))
+                .find(|(k, _)| *k == name)
+                .map(|(_, v)| v)
+        })
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: ByteBuf,
+}
+
+impl HttpResponse {
+    /// Creates a response with a `Content-Type` header.
+    pub fn new(status_code: u16, content_type: &str, body: Vec<u8>) -> Self {
+        Self {
+            status_code,
+            headers: vec![
+                ("Content-Type".to_string(), content_type.to_string()),
+                ("Content-Length".to_string(), body.len().to_string()),
+            ],
+            body: ByteBuf::from(body),
+        }
+    }
+
+    /// Creates a plain text response.
+    pub fn text(status_code: u16, body: impl Into<String>) -> Self {
+        Self::new(
+            status_code,
+            "text/plain; charset=utf-8",
+            body.into().into_bytes(),
+        )
+    }
+
+    /// Creates a JSON response.
+    pub fn json(status_code: u16, body: &serde_json::Value) -> Self {
+        Self::new(
+            status_code,
+            "application/json",
+            body.to_string().into_bytes(),
+        )
+    }
+}
+
+/// Handler of a user-defined route.
+pub type HttpHandler = fn(&HttpRequest) -> HttpResponse;
+
+thread_local! {
+    static ROUTES: RefCell<BTreeMap<String, HttpHandler>> = RefCell::default();
+    static LOGS_ROUTE_ENABLED: Cell<bool> = Cell::default();
+}
+
+/// Registers a handler for the given path, e.g. `/balance`.
+/// Call this in both the init and post-upgrade hooks.
+pub fn register_route(path: &str, handler: HttpHandler) {
+    ROUTES.with(|r| r.borrow_mut().insert(path.to_string(), handler));
+}
+
+/// Enables or disables the public `/logs` route.
+/// Call this in both the init and post-upgrade hooks.
+pub fn set_logs_route_enabled(enabled: bool) {
+    LOGS_ROUTE_ENABLED.with(|e| e.set(enabled));
+}
+
+/// Serves HTTP requests from the HTTP gateway.
+#[query]
+pub fn http_request(req: HttpRequest) -> HttpResponse {
+    if let Some(handler) = ROUTES.with(|r| r.borrow().get(req.path()).copied()) {
+        return handler(&req);
+    }
+    if req.method != "GET" {
+        return HttpResponse::text(405, "Method not allowed");
+    }
+    match req.path() {
+        "/metrics" => HttpResponse::new(200, "text/plain; version=0.0.4", metrics().into_bytes()),
+        "/status" => HttpResponse::json(200, &status()),
+        #[cfg(feature = "logging")]
+        "/logs" if LOGS_ROUTE_ENABLED.with(|e| e.get()) => logs(&req),
+        _ => HttpResponse::text(404, "Not found"),
+    }
+}
+
+// Writes a gauge in the Prometheus text format.
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+fn metrics() -> String {
+    let mut out = String::new();
+    gauge(
+        &mut out,
+        "rustic_cycles_balance",
+        "Cycles balance of the canister.",
+        canister_balance128(),
+    );
+    gauge(
+        &mut out,
+        "rustic_stable_memory_pages",
+        "Size of the stable memory in pages of 64 KiB.",
+        canister_stable_pages(),
+    );
+    gauge(
+        &mut out,
+        "rustic_heap_memory_bytes",
+        "Size of the heap memory in bytes.",
+        canister_heap_size(),
+    );
+    #[cfg(feature = "pausable")]
+    gauge(
+        &mut out,
+        "rustic_paused",
+        "Whether the canister is paused.",
+        is_paused() as u8,
+    );
+    #[cfg(feature = "lifecycle")]
+    {
+        let version = crate::lifecycle::get_version();
+        gauge(
+            &mut out,
+            "rustic_version_major",
+            "Major version of the canister.",
+            version.version_major,
+        );
+        gauge(
+            &mut out,
+            "rustic_version_minor",
+            "Minor version of the canister.",
+            version.version_minor,
+        );
+        gauge(
+            &mut out,
+            "rustic_version_patch",
+            "Patch version of the canister.",
+            version.version_patch,
+        );
+        gauge(
+            &mut out,
+            "rustic_stable_memory_version",
+            "Version of the stable memory layout.",
+            version.stable_memory_version,
+        );
+        gauge(
+            &mut out,
+            "rustic_last_upgraded_seconds",
+            "Time of the last upgrade in seconds since the epoch.",
+            version.last_upgraded / 1_000_000_000,
+        );
+    }
+    #[cfg(feature = "inspection")]
+    write_instruction_histograms(&mut out);
+    gauge(
+        &mut out,
+        "rustic_timestamp_seconds",
+        "Current time in seconds since the epoch.",
+        canister_time() / 1_000_000_000,
+    );
+    out
+}
+
+// Escapes a Prometheus label value.
+#[cfg(feature = "inspection")]
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Writes the instruction histograms as a Prometheus histogram with cumulative buckets.
+#[cfg(feature = "inspection")]
+fn write_instruction_histograms(out: &mut String) {
+    use crate::inspection::*;
+
+    let name = "rustic_method_instructions";
+    let _ = writeln!(out, "# HELP {name} Instructions used by update methods.");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    for (method, histogram) in instruction_histograms() {
+        let method = escape_label(&method);
+        let mut cumulative = 0;
+        for (i, count) in histogram.buckets.iter().enumerate() {
+            cumulative += count;
+            let le = INSTRUCTION_BUCKETS
+                .get(i)
+                .map(|b| b.to_string())
+                .unwrap_or("+Inf".to_string());
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{method=\"{method}\",le=\"{le}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(out, "{name}_sum{{method=\"{method}\"}} {}", histogram.sum);
+        let _ = writeln!(
+            out,
+            "{name}_count{{method=\"{method}\"}} {}",
+            histogram.count
+        );
+    }
+}
+
+fn status() -> serde_json::Value {
+    #[allow(unused_mut)]
+    let mut status = serde_json::json!({
+        "canister_id": canister_id().to_text(),
+        "cycles": canister_balance128().to_string(),
+        "timestamp": canister_time(),
+    });
+    #[cfg(feature = "pausable")]
+    {
+        status["paused"] = is_paused().into();
+    }
+    #[cfg(feature = "lifecycle")]
+    {
+        status["version"] = crate::lifecycle::get_version_text().into();
+    }
+    status
+}
+
+#[cfg(feature = "logging")]
+fn logs(req: &HttpRequest) -> HttpResponse {
+    use crate::logging::*;
+
+    let filter = LogFilter {
+        start: req.query_param("start").and_then(|s| s.parse().ok()),
+        ..Default::default()
+    };
+    let limit = req
+        .query_param("limit")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(MAX_LOG_PAGE_SIZE);
+    let page = match req.query_param("source").unwrap_or("log") {
+        "log" => logs_page(&filter, limit),
+        "trace" => traces_page(&filter, limit),
+        #[cfg(feature = "stable-logging")]
+        "stable" => crate::logging_stable::stable_logs_page(&filter, limit),
+        _ => return HttpResponse::text(400, "Unknown log source"),
+    };
+    match serde_json::to_value(page) {
+        Ok(body) => HttpResponse::json(200, &body),
+        Err(e) => HttpResponse::text(500, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn get(url: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: vec![],
+            body: ByteBuf::new(),
+        }
+    }
+
+    fn body(res: &HttpResponse) -> String {
+        String::from_utf8(res.body.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_query_param() {
+        let req = get("/logs?start=5&source=trace");
+        assert_eq!(req.path(), "/logs");
+        assert_eq!(req.query_param("start"), Some("5"));
+        assert_eq!(req.query_param("source"), Some("trace"));
+        assert_eq!(req.query_param("limit"), None);
+    }
+
+    #[test]
+    fn test_metrics() {
+        set_mock_balance(42);
+        set_mock_stable_pages(3);
+        set_mock_heap_size(65536);
+        let res = http_request(get("/metrics"));
+        assert_eq!(res.status_code, 200);
+        let body = body(&res);
+        assert!(body.contains("\nrustic_cycles_balance 42\n"));
+        assert!(body.contains("\nrustic_stable_memory_pages 3\n"));
+        assert!(body.contains("\nrustic_heap_memory_bytes 65536\n"));
+        #[cfg(feature = "pausable")]
+        assert!(body.contains("\nrustic_paused 0\n"));
+    }
+
+    #[cfg(feature = "inspection")]
+    #[test]
+    fn test_metrics_instruction_histograms() {
+        crate::inspection::record_instructions("transfer", 5_000);
+        crate::inspection::record_instructions("transfer", 50_000);
+        let body = body(&http_request(get("/metrics")));
+        assert!(body
+            .contains("\nrustic_method_instructions_bucket{method=\"transfer\",le=\"10000\"} 1\n"));
+        assert!(body
+            .contains("\nrustic_method_instructions_bucket{method=\"transfer\",le=\"+Inf\"} 2\n"));
+        assert!(body.contains("\nrustic_method_instructions_count{method=\"transfer\"} 2\n"));
+
+        crate::inspection::record_instructions("a\"b\\c\nd", 1);
+        let escaped = self::body(&http_request(get("/metrics")));
+        assert!(
+            escaped.contains("\nrustic_method_instructions_count{method=\"a\\\"b\\\\c\\nd\"} 1\n")
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        let res = http_request(get("/status"));
+        assert_eq!(res.status_code, 200);
+        let status: serde_json::Value = serde_json::from_slice(&res.body).unwrap();
+        assert_eq!(status["canister_id"], MOCK_ID);
+        #[cfg(feature = "pausable")]
+        assert_eq!(status["paused"], false);
+    }
+
+    #[test]
+    fn test_routes() {
+        assert_eq!(http_request(get("/unknown")).status_code, 404);
+        assert_eq!(http_request(get("/logs")).status_code, 404);
+
+        register_route("/hello", |req| {
+            HttpResponse::text(200, format!("hello {}", req.method))
+        });
+        let res = http_request(HttpRequest {
+            method: "POST".to_string(),
+            ..get("/hello?name=x")
+        });
+        assert_eq!(res.status_code, 200);
+        assert_eq!(body(&res), "hello POST");
+
+        let res = http_request(HttpRequest {
+            method: "POST".to_string(),
+            ..get("/metrics")
+        });
+        assert_eq!(res.status_code, 405);
+    }
+
+    #[cfg(feature = "logging")]
+    #[test]
+    fn test_logs() {
+        set_logs_route_enabled(true);
+        let res = http_request(get("/logs?limit=10"));
+        assert_eq!(res.status_code, 200);
+        let page: serde_json::Value = serde_json::from_slice(&res.body).unwrap();
+        assert_eq!(page["entries"], serde_json::json!([]));
+        assert_eq!(page["next"], serde_json::Value::Null);
+        assert_eq!(http_request(get("/logs?source=trace")).status_code, 200);
+        assert_eq!(http_request(get("/logs?source=other")).status_code, 400);
+    }
+
+    #[cfg(feature = "stable-logging")]
+    #[test]
+    fn test_stable_logs() {
+        set_logs_route_enabled(true);
+        crate::logging_stable::stable_log_append(crate::logging::LogEntry {
+            index: 0,
+            timestamp: 0,
+            level: crate::logging::LogLevel::Info,
+            target: "app".to_string(),
+            file: None,
+            line: None,
+            span: None,
+            message: "persisted".to_string(),
+            fields: vec![],
+        });
+        let res = http_request(get("/logs?source=stable"));
+        assert_eq!(res.status_code, 200);
+        let page: serde_json::Value = serde_json::from_slice(&res.body).unwrap();
+        assert_eq!(page["entries"][0]["message"], "persisted");
+    }
+}
@@ -0,0 +1,206 @@
+#![cfg(feature = "inspection")]
+
+//! Per-method instruction histograms.
+//!
+//! Declare `_span = InstructionSpan::new("method_name")` at the beginning of an update method
+//! to record the number of instructions it uses.
+//! The instructions are counted for the whole call context, so async methods are measured across `await`s.
+//! State changes of query methods are discarded, so only update methods can be inspected.
+//!
+//! # Examples
+//! ```
+//! use rustic::inspection::InstructionSpan;
+//! pub fn some_func() {
+//!     let _span = InstructionSpan::new("some_func");
+//!     // measured code
+//! }
+//! ```
+//!
+//! # Attention
+//! The variable name must be `_span` or `_some_text` and not `_` in order for the drop checker to be properly scoped.
+
+use crate::access_control::*;
+use crate::error::*;
+use crate::memory_map::*;
+#[cfg(test)]
+use crate::testing::*;
+use crate::types::*;
+use crate::utils::*;
+use candid::CandidType;
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use rustic_macros::modifiers;
+use std::cell::RefCell;
+
+/// Upper bounds (inclusive) of the histogram buckets.
+/// An additional last bucket counts calls using more instructions than the largest bound.
+pub const INSTRUCTION_BUCKETS: [u64; 7] = [
+    10_000,
+    100_000,
+    1_000_000,
+    10_000_000,
+    100_000_000,
+    1_000_000_000,
+    10_000_000_000,
+];
+
+// The performance counter type for instructions executed in the current call context.
+const CALL_CONTEXT_INSTRUCTION_COUNTER: u32 = 1;
+
+#[derive(Clone, CandidType, serde::Serialize, serde::Deserialize)]
+pub struct InstructionHistogram {
+    /// Number of calls in each bucket of [`INSTRUCTION_BUCKETS`], followed by the overflow bucket.
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum: u128,
+    pub min: u64,
+    pub max: u64,
+}
+
+impl Default for InstructionHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; INSTRUCTION_BUCKETS.len() + 1],
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+}
+
+impl InstructionHistogram {
+    fn record(&mut self, instructions: u64) {
+        let bucket = INSTRUCTION_BUCKETS
+            .iter()
+            .position(|b| instructions <= *b)
+            .unwrap_or(INSTRUCTION_BUCKETS.len());
+        if let Some(b) = self.buckets.get_mut(bucket) {
+            *b += 1;
+        }
+        self.count += 1;
+        self.sum += instructions as u128;
+        self.min = self.min.min(instructions);
+        self.max = self.max.max(instructions);
+    }
+}
+
+thread_local! {
+    // can be lazily initialized
+    // mapping from method name to its instruction histogram
+    static INSTRUCTION_HISTOGRAMS: RefCell<StableBTreeMap<String, Cbor<InstructionHistogram>, VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(INSPECTION_MEM_ID)))
+    });
+}
+
+/// Records the instructions used by a method into its histogram when dropped.
+pub struct InstructionSpan {
+    method: String,
+    start: u64,
+}
+
+impl InstructionSpan {
+    pub fn new(method: &str) -> Self {
+        Self {
+            method: method.to_string(),
+            start: performance_counter(CALL_CONTEXT_INSTRUCTION_COUNTER),
+        }
+    }
+}
+
+impl Drop for InstructionSpan {
+    fn drop(&mut self) {
+        let instructions =
+            performance_counter(CALL_CONTEXT_INSTRUCTION_COUNTER).saturating_sub(self.start);
+        record_instructions(&self.method, instructions);
+    }
+}
+
+/// Records the instructions used by one call of a method.
+pub fn record_instructions(method: &str, instructions: u64) {
+    INSTRUCTION_HISTOGRAMS.with(|h| {
+        let mut h = h.borrow_mut();
+        let mut histogram = h.get(&method.to_string()).map(|x| x.0).unwrap_or_default();
+        histogram.record(instructions);
+        h.insert(method.to_string(), Cbor(histogram));
+    });
+}
+
+pub(crate) fn instruction_histograms() -> Vec<(String, InstructionHistogram)> {
+    INSTRUCTION_HISTOGRAMS.with(|h| h.borrow().iter().map(|(k, v)| (k, v.0)).collect())
+}
+
+/// Returns the instruction histograms of all inspected methods. Must be called by admins.
+#[query]
+#[modifiers("only_admin")]
+pub fn get_instruction_histograms() -> Vec<(String, InstructionHistogram)> {
+    instruction_histograms()
+}
+
+/// Resets the instruction histograms of all methods. Must be called by admins.
+#[update]
+#[modifiers("only_admin")]
+pub fn reset_instruction_histograms() {
+    unwrap_or_reject(try_reset_instruction_histograms())
+}
+
+/// Same as [`reset_instruction_histograms`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_reset_instruction_histograms() -> Result<(), RusticError> {
+    check_admin()?;
+    INSTRUCTION_HISTOGRAMS.with(|h| h.borrow_mut().clear_new());
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use candid::Principal;
+
+    #[test]
+    fn test_instruction_span() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+
+        for (start, end) in [(1_000, 6_000), (1_000, 501_000), (0, 20_000_000_000)] {
+            set_mock_instruction_counter(start);
+            let _span = InstructionSpan::new("transfer");
+            set_mock_instruction_counter(end);
+        }
+        {
+            let _span = InstructionSpan::new("approve");
+        }
+
+        let histograms = get_instruction_histograms();
+        assert_eq!(histograms.len(), 2);
+        assert_eq!(histograms[0].0, "approve");
+        assert_eq!(histograms[0].1.count, 1);
+        assert_eq!(histograms[0].1.buckets[0], 1);
+        let transfer = &histograms[1].1;
+        assert_eq!(transfer.buckets, vec![1, 0, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(transfer.count, 3);
+        assert_eq!(transfer.sum, 20_000_505_000);
+        assert_eq!(transfer.min, 5_000);
+        assert_eq!(transfer.max, 20_000_000_000);
+
+        reset_instruction_histograms();
+        assert!(get_instruction_histograms().is_empty());
+
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        assert_eq!(
+            try_reset_instruction_histograms(),
+            Err(RusticError::NotAdmin)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "msg_reject should only be called inside canisters")]
+    fn test_reset_unauth() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        reset_instruction_histograms();
+    }
+}
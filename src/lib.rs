@@ -5,13 +5,20 @@
 pub use rustic_macros::*;
 
 pub mod access_control;
+pub mod audit;
+pub mod caller_lists;
+pub mod error;
 mod global_flags;
+pub mod https;
+pub mod inspection;
 pub mod inter_canister;
 pub mod lifecycle;
 pub mod logging;
 pub mod logging_stable;
 pub mod memory_map;
+pub mod multisig;
 pub mod pausable;
+pub mod permissions;
 pub mod reentrancy_guard;
 pub mod testing;
 pub mod types;
@@ -38,8 +45,28 @@ pub fn rustic_init() {
     crate::access_control::access_init(canister_caller());
     #[cfg(feature = "lifecycle")]
     crate::lifecycle::canister_lifecycle_init();
+    #[cfg(feature = "audit-events")]
+    crate::audit::audit_certify();
     #[cfg(feature = "logging")]
-    crate::logging::init(true); // Trace always enabled
+    crate::logging::init();
+}
+
+/// Pre-upgrade hook for Rustic. Needs to be called in the pre-upgrade hook of every canister.
+/// # Example
+/// ```rust
+/// # use ic_cdk::pre_upgrade;
+/// #[pre_upgrade]
+/// pub fn pre_upgrade () {
+///   // your own pre-upgrade code
+///
+///   rustic::rustic_pre_upgrade();
+/// }
+/// ```
+pub fn rustic_pre_upgrade() {
+    #[cfg(feature = "logging")]
+    if crate::logging::logging_initialized() {
+        crate::logging::pre_upgrade();
+    }
 }
 
 /// Post-upgrade hook for Rustic. Needs to be called in the post-upgrade hook of every canister.
@@ -60,10 +87,46 @@ pub fn rustic_post_upgrade(
 ) {
     #[cfg(feature = "lifecycle")]
     crate::lifecycle::lifecycle_on_upgrade(stable_memory_bump, major_bump, minor_bump);
+    #[cfg(feature = "access")]
+    crate::access_control::admins_migrate();
+    #[cfg(feature = "access-roles")]
+    {
+        crate::access_control::access_roles_migrate();
+        crate::access_control::role_members_init();
+        crate::access_control::role_expiry_queue_init();
+    }
+    #[cfg(feature = "audit-events")]
+    crate::audit::audit_certify();
+    #[cfg(feature = "logging")]
+    if crate::logging::logging_initialized() {
+        crate::logging::init(); // The logger does not survive upgrades
+    }
 }
 
+#[cfg(all(feature = "access", feature = "export-candid"))]
+use crate::access_control::AdminsPage;
+#[cfg(all(feature = "access-roles", feature = "export-candid"))]
+use crate::access_control::{
+    RoleDelegation, RoleGrant, RoleInfo, RoleMembersPage, RolesBatchResult,
+};
+#[cfg(all(feature = "audit-events", feature = "export-candid"))]
+use crate::audit::{AuditCertificate, AuditPage};
+#[cfg(all(feature = "caller-lists", feature = "export-candid"))]
+use crate::caller_lists::CallerListPage;
+#[cfg(feature = "export-candid")]
+use crate::error::RusticError;
+#[cfg(all(feature = "https", feature = "export-candid"))]
+use crate::https::{HttpRequest, HttpResponse};
+#[cfg(all(feature = "inspection", feature = "export-candid"))]
+use crate::inspection::InstructionHistogram;
 #[cfg(all(feature = "lifecycle", feature = "export-candid"))]
 use crate::lifecycle::CanisterLifecycle;
+#[cfg(all(feature = "logging", feature = "export-candid"))]
+use crate::logging::{LogFilter, LogLevel, LogPage, LogSink, LoggingConfig};
+#[cfg(all(feature = "multisig", feature = "export-candid"))]
+use crate::multisig::{MultisigConfig, Proposal, ProposalAction, ProposalStatus};
+#[cfg(all(feature = "permissions", feature = "export-candid"))]
+use crate::permissions::{Permission, PermissionsPage};
 #[cfg(feature = "export-candid")]
 use candid::Principal;
 #[cfg(feature = "export-candid")]
@@ -1,7 +1,10 @@
 #![cfg(feature = "lifecycle")]
 //! Canister Lifecycle Management
 //!
+//! With the `audit-events` feature, every upgrade is recorded in the audit log with the new version.
 
+#[cfg(feature = "audit-events")]
+use crate::audit::*;
 use crate::memory_map::*;
 #[cfg(test)]
 use crate::testing::*;
@@ -14,12 +17,12 @@ use std::cell::RefCell;
 
 #[derive(Default, Clone, CandidType, serde::Serialize, serde::Deserialize)]
 pub struct CanisterLifecycle {
-    stable_memory_version: u16,
-    version_major: u16,
-    version_minor: u16,
-    version_patch: u16,
-    last_upgraded: u64,
-    ic_canister_version: u64,
+    pub(crate) stable_memory_version: u16,
+    pub(crate) version_major: u16,
+    pub(crate) version_minor: u16,
+    pub(crate) version_patch: u16,
+    pub(crate) last_upgraded: u64,
+    pub(crate) ic_canister_version: u64,
 }
 
 impl std::fmt::Display for CanisterLifecycle {
@@ -81,6 +84,8 @@ pub(crate) fn lifecycle_on_upgrade(stable_memory_bump: bool, major_bump: bool, m
         }
         lifecycle.last_upgraded = canister_time();
         lifecycle.ic_canister_version = canister_version();
+        #[cfg(feature = "audit-events")]
+        emit(AuditAction::Upgraded(lifecycle.to_string()), None);
         #[allow(clippy::expect_used)] // unwrap desired
         l.set(Cbor(Some(lifecycle)))
             .expect("Lifecycle update failed");
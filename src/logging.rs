@@ -7,69 +7,312 @@
 //! Logs are stored in a circular buffer, which can be exported as a list of `LogEntry` structs.
 //! The buffer is in the heap, so it is not persisted across canister upgrades.
 //! For persistent logging, use the `stable-logging` feature.
+//!
+//! To keep the log lines around an upgrade, the last [`PRESERVED_LOG_ENTRIES`] log and trace entries
+//! are copied into a bounded region of stable memory by `rustic_pre_upgrade`,
+//! and restored into the heap buffers when the logger is reinstalled in `rustic_post_upgrade`.
+//!
+//! The max level of each sink, the capacity of the heap buffers and whether tracing is enabled
+//! are stored in stable memory, and can be changed by admins at runtime.
+//!
+//! A trap reverts all state changes of the message, so a panic cannot write to these logs itself.
+//! Methods run in a follow-up message with [`call_recording_panics`] have their panic message, location,
+//! method and caller recorded by the calling message instead, see [`PanicGuard`].
+//!
+//! Every entry carries a sequence `index` that keeps increasing even when older entries are evicted.
+//! The admin-only query methods [`get_logs`] and [`get_traces`] page through the buffers using this index as a cursor.
 
-use crate::utils::canister_time;
+use crate::access_control::*;
+use crate::error::*;
+use crate::global_flags::*;
+use crate::memory_map::*;
+#[cfg(test)]
+use crate::testing::*;
+use crate::types::*;
+use crate::utils::*;
 
-use candid::CandidType;
+use candid::{CandidType, Principal};
+use ic_cdk::api::call::{CallResult, RejectionCode};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use rustic_macros::modifiers;
 use serde::{Deserialize, Serialize};
 use std::cell::{Cell, RefCell};
 use std::collections::VecDeque;
-use std::io::Write;
-use tracing::Level;
-use tracing_subscriber::fmt::format::{FmtSpan, Writer};
-use tracing_subscriber::fmt::time::FormatTime;
-use tracing_subscriber::fmt::writer::MakeWriterExt;
-use tracing_subscriber::fmt::Layer;
-use tracing_subscriber::layer::SubscriberExt;
+use std::fmt::Write;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::Registry;
 
+/// Default capacity of the heap log and trace buffers.
+pub const DEFAULT_LOG_CAPACITY: u64 = 100;
+/// Number of the most recent log and trace entries preserved across upgrades.
+pub const PRESERVED_LOG_ENTRIES: u64 = 100;
+
+/// Runtime logging configuration, persisted in stable memory.
+#[derive(Clone, CandidType, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// Max level recorded in the heap log.
+    pub log_level: LogLevel,
+    /// Max level recorded in the heap trace.
+    pub trace_level: LogLevel,
+    /// Max level recorded in the stable log. Only used with the `stable-logging` feature.
+    pub stable_log_level: LogLevel,
+    pub log_capacity: u64,
+    pub trace_capacity: u64,
+    pub trace_enabled: bool,
+    /// Max number of entries of the stable log. Only used with the `stable-logging` feature.
+    #[serde(default = "default_stable_log_capacity")]
+    pub stable_log_capacity: u64,
+}
+
+fn default_stable_log_capacity() -> u64 {
+    u64::MAX
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            log_level: LogLevel::Info,
+            trace_level: LogLevel::Trace,
+            stable_log_level: LogLevel::Info,
+            log_capacity: DEFAULT_LOG_CAPACITY,
+            trace_capacity: DEFAULT_LOG_CAPACITY,
+            trace_enabled: true,
+            stable_log_capacity: default_stable_log_capacity(),
+        }
+    }
+}
+
 thread_local! {
     static INITIALIZED: Cell<bool> = Cell::default();
+    // Sequence indices of the next log and trace entries, persisted in the pre-upgrade hook.
+    static LOG_INDEX: Cell<u64> = Cell::default();
+    static TRACE_INDEX: Cell<u64> = Cell::default();
+    // Method name and caller of the most recently created `PanicGuard`.
+    static PANIC_CONTEXT: RefCell<Option<(String, Principal)>> = RefCell::default();
     static LOG: RefCell<LogBuffer> = RefCell::new(LogBuffer::default());
     static TRACE: RefCell<LogBuffer> = RefCell::new(LogBuffer::default());
+    static LOGGING_CONFIG: RefCell<StableCell<Cbor<Option<LoggingConfig>>, RM>> =
+        #[allow(clippy::expect_used)] // safe unwrap during init
+        RefCell::new(StableCell::init(
+            RM::new(DefaultMemoryImpl::default(), LOGGING_CONFIG_PAGE_START..LOGGING_CONFIG_PAGE_END),
+            Cbor(Some(LoggingConfig::default())),
+        ).expect("Failed to initialize the logging config cell")
+    );
+    // can be lazily initialized
+    // mapping from entry index to the most recent entries of the heap log and trace
+    static LOG_UPGRADE_BUFFER: RefCell<StableBTreeMap<u64, Cbor<LogEntry>, VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(LOG_UPGRADE_BUFFER_MEM_ID)))
+    });
+    static TRACE_UPGRADE_BUFFER: RefCell<StableBTreeMap<u64, Cbor<LogEntry>, VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(TRACE_UPGRADE_BUFFER_MEM_ID)))
+    });
 }
 
-pub fn init(enable_trace: bool) {
+/// Installs the logger. The logging configuration is read from stable memory,
+/// so this can be called both on init and after upgrades.
+pub fn init() {
     if INITIALIZED.with(|i| i.replace(true)) {
         panic!("Logger already initialized");
     }
 
-    let log_layer = Layer::default()
-        .with_writer((|| LogWriter::new(false)).with_max_level(Level::INFO))
-        .json()
-        .with_timer(Timer {})
-        .with_file(true)
-        .with_line_number(true)
-        .with_current_span(false)
-        .with_span_list(false);
+    // Remember that logging is enabled so that the logger is reinstalled after upgrades.
+    GLOBAL_FLAGS.with(|f| {
+        let mut f = f.borrow_mut();
+        #[allow(clippy::unwrap_used)] // unwrap desired
+        let mut flags = f.get().0.clone().unwrap();
+        if !flags.logging_initialized {
+            flags.logging_initialized = true;
+            #[allow(clippy::expect_used)] // unwrap desired
+            f.set(Cbor(Some(flags))).expect("Logging init failed");
+        }
+    });
+
+    restore_indices();
+    let config = get_config();
+    LOG.with_borrow_mut(|l| l.set_max_capacity(config.log_capacity as usize));
+    TRACE.with_borrow_mut(|t| t.set_max_capacity(config.trace_capacity as usize));
+    restore_preserved_entries();
+
+    #[cfg(feature = "stable-logging")]
+    crate::logging_stable::stable_log_init();
+    #[cfg(feature = "stable-logging")]
+    let stable_layer = LogLayer::new(LogSink::Stable);
+    #[cfg(not(feature = "stable-logging"))]
+    let stable_layer = tracing_subscriber::layer::Identity::new();
+
+    let log_layer = LogLayer::new(LogSink::Log);
+    let trace_layer = LogLayer::new(LogSink::Trace);
+
+    Registry::default()
+        .with(log_layer)
+        .with(trace_layer)
+        .with(stable_layer)
+        .init();
+
+    set_panic_hook();
+}
+
+// Replaces the panic hook of `ic_cdk` with one that also reports the method and caller.
+fn set_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let message = match info.payload().downcast_ref::<&'static str>() {
+            Some(s) => *s,
+            None => match info.payload().downcast_ref::<String>() {
+                Some(s) => &s[..],
+                None => "Box<Any>",
+            },
+        };
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()))
+            .unwrap_or_default();
+        let context = PANIC_CONTEXT
+            .with(|c| c.try_borrow().ok().and_then(|c| c.clone()))
+            .map(|(method, caller)| format!(" in method '{}' called by {}", method, caller))
+            .unwrap_or_default();
+        let record = format!(
+            "Panicked at '{}', {}{} (time {})",
+            message,
+            location,
+            context,
+            canister_time()
+        );
+        canister_print(&record);
+        ic_cdk::api::trap(&record);
+    }));
+}
+
+/// Provides the method and caller of panics, and records trapped async methods.
+///
+/// The panic hook installed by [`init`] prints the panic message, location, method and caller
+/// into the canister log kept by the replica, and traps with the same text.
+/// The method and caller are taken from the most recently created guard.
+///
+/// A trap reverts all state changes of the current message execution, including the logs of this module,
+/// and timers and outgoing calls made while panicking. The trap text is however returned to the caller
+/// in the reject message, so a method called with [`call_recording_panics`] has its panic recorded
+/// into the logs by the calling message, which is not reverted.
+///
+/// For async methods, the IC only reverts the execution after the last `await`.
+/// The guard is then dropped in the cleanup callback, whose state changes are kept,
+/// and records an error entry with the method and caller into the logs.
+/// The panic message is not known in the cleanup callback, so the entry refers to the canister log.
+///
+/// # Example
+/// ```rust
+/// # use rustic::logging::PanicGuard;
+/// pub async fn some_func() {
+///     let _guard = PanicGuard::new(rustic::function!());
+///     // code that may panic
+/// }
+/// ```
+pub struct PanicGuard {
+    method: String,
+    caller: Principal,
+    previous: Option<(String, Principal)>,
+}
+
+impl PanicGuard {
+    pub fn new(method: &str) -> Self {
+        let caller = canister_caller();
+        let previous = PANIC_CONTEXT.with(|c| c.borrow_mut().replace((method.to_string(), caller)));
+        Self {
+            method: method.to_string(),
+            caller,
+            previous,
+        }
+    }
+}
+
+impl Drop for PanicGuard {
+    fn drop(&mut self) {
+        if is_recovering_from_trap() {
+            tracing::error!(
+                target: "rustic::panic",
+                method = self.method.as_str(),
+                caller = %self.caller,
+                "Method trapped, see the canister log for the panic message"
+            );
+        }
+        PANIC_CONTEXT.with(|c| *c.borrow_mut() = self.previous.take());
+    }
+}
 
-    if enable_trace {
-        let trace_layer = Layer::default()
-            .with_writer(|| LogWriter::new(true))
-            .json()
-            .with_timer(Timer {})
-            .with_file(true)
-            .with_line_number(true)
-            .with_current_span(false)
-            .with_span_events(FmtSpan::ENTER);
+/// Calls a method of this canister in a follow-up message,
+/// and records the panic message, location, method and caller into the logs if the method traps.
+///
+/// The arguments and the reply are Candid encoded, e.g. with `candid::encode_args` and `candid::decode_one`.
+/// The called method sees this canister as its caller, so the caller of the current message is recorded instead.
+///
+/// # Example
+/// ```rust
+/// # use rustic::logging::call_recording_panics;
+/// pub async fn transfer(amount: u64) -> Result<(), String> {
+///     let args = candid::encode_one(amount).map_err(|e| e.to_string())?;
+///     call_recording_panics("transfer_unchecked", args)
+///         .await
+///         .map(|_| ())
+///         .map_err(|(_, message)| message)
+/// }
+/// ```
+pub async fn call_recording_panics(method: &str, args: Vec<u8>) -> CallResult<Vec<u8>> {
+    let caller = canister_caller();
+    let result = ic_cdk::api::call::call_raw128(canister_id(), method, args, 0).await;
+    record_trap(method, caller, &result);
+    result
+}
 
-        Registry::default().with(log_layer).with(trace_layer).init();
-    } else {
-        Registry::default().with(log_layer).init();
+// Records the reject message of a call that trapped, which holds the panic record of the panic hook.
+fn record_trap(method: &str, caller: Principal, result: &CallResult<Vec<u8>>) {
+    if let Err((RejectionCode::CanisterError, message)) = result {
+        tracing::error!(
+            target: "rustic::panic",
+            method,
+            caller = %caller,
+            "{message}"
+        );
     }
 }
 
-// pub fn init_with_logs(enable_trace: bool, logs: Vec<LogEntry>, traces: Vec<LogEntry>) {
-//     init(enable_trace);
+/// Returns whether the logger was enabled during init.
+pub(crate) fn logging_initialized() -> bool {
+    #[allow(clippy::unwrap_used)] // unwrap desired
+    GLOBAL_FLAGS.with(|f| f.borrow().get().0.clone().unwrap().logging_initialized)
+}
 
-//     for log in logs {
-//         LOG.with_borrow_mut(|l| l.append(log));
-//     }
-//     for trace in traces {
-//         TRACE.with_borrow_mut(|t| t.append(trace));
-//     }
-// }
+// Restores the entries preserved before the last upgrade into the heap buffers.
+fn restore_preserved_entries() {
+    LOG_UPGRADE_BUFFER
+        .with_borrow(|b| LOG.with_borrow_mut(|l| b.iter().for_each(|(_, e)| l.append(e.0))));
+    TRACE_UPGRADE_BUFFER
+        .with_borrow(|b| TRACE.with_borrow_mut(|t| b.iter().for_each(|(_, e)| t.append(e.0))));
+}
+
+// Replaces the contents of an upgrade buffer with the most recent entries of a heap buffer.
+fn preserve_entries(
+    buffer: &RefCell<StableBTreeMap<u64, Cbor<LogEntry>, VM>>,
+    entries: &LogBuffer,
+) {
+    let mut b = buffer.borrow_mut();
+    b.clear_new();
+    let skip = entries
+        .entries
+        .len()
+        .saturating_sub(PRESERVED_LOG_ENTRIES as usize);
+    for entry in entries.iter().skip(skip) {
+        b.insert(entry.index, Cbor(entry.clone()));
+    }
+}
 
 /// A circular buffer for log messages.
 pub struct LogBuffer {
@@ -86,8 +329,20 @@ impl LogBuffer {
         }
     }
 
+    /// Changes the max capacity of the buffer, evicting the oldest entries if needed.
+    pub fn set_max_capacity(&mut self, max_capacity: usize) {
+        self.max_capacity = max_capacity;
+        while self.entries.len() > self.max_capacity {
+            self.entries.pop_front();
+        }
+    }
+
     /// Adds a new entry to the buffer, potentially evicting older entries.
+    /// A buffer with a max capacity of 0 drops the entry.
     pub fn append(&mut self, entry: LogEntry) {
+        if self.max_capacity == 0 {
+            return;
+        }
         while self.entries.len() >= self.max_capacity {
             self.entries.pop_front();
         }
@@ -103,7 +358,7 @@ impl LogBuffer {
 impl Default for LogBuffer {
     fn default() -> Self {
         LogBuffer {
-            max_capacity: 100,
+            max_capacity: DEFAULT_LOG_CAPACITY as usize,
             entries: VecDeque::new(),
         }
     }
@@ -117,57 +372,758 @@ pub fn export_traces() -> Vec<LogEntry> {
     TRACE.with_borrow(|t| t.iter().cloned().collect())
 }
 
+/// Maximum number of entries returned in a single page.
+pub const MAX_LOG_PAGE_SIZE: u64 = 1000;
+/// Maximum number of entries examined for a single page.
+/// Queries stop early and return a cursor when this limit is hit, to stay within the instruction limit.
+pub const MAX_LOG_PAGE_SCAN: u64 = 10_000;
+
+/// A structured log entry.
 #[derive(CandidType, Serialize, Deserialize, Clone)]
 pub struct LogEntry {
+    pub index: u64,
     pub timestamp: u64,
+    pub level: LogLevel,
+    pub target: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// Name of the span the entry was recorded in.
+    pub span: Option<String>,
     pub message: String,
+    /// Key/value fields other than `message`, formatted with `Debug`.
+    pub fields: Vec<(String, String)>,
 }
 
-struct LogWriter {
-    trace: bool,
-    buffer: Vec<u8>,
+/// Log levels, ordered from the least to the most verbose.
+#[derive(
+    CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
 }
 
-impl LogWriter {
-    fn new(trace: bool) -> LogWriter {
-        LogWriter {
-            trace,
-            buffer: Vec::new(),
+impl From<&Level> for LogLevel {
+    fn from(level: &Level) -> Self {
+        match *level {
+            Level::ERROR => LogLevel::Error,
+            Level::WARN => LogLevel::Warn,
+            Level::INFO => LogLevel::Info,
+            Level::DEBUG => LogLevel::Debug,
+            Level::TRACE => LogLevel::Trace,
         }
     }
 }
 
-impl Write for LogWriter {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.buffer.extend(buf);
-        Ok(buf.len())
+/// Filter for log queries. Unset fields match all entries.
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct LogFilter {
+    /// Index of the first entry to return. Use the `next` cursor of the previous page to continue.
+    pub start: Option<u64>,
+    /// Only entries with a timestamp at or after this time (in nanoseconds).
+    pub from_timestamp: Option<u64>,
+    /// Only entries with a timestamp at or before this time (in nanoseconds).
+    pub to_timestamp: Option<u64>,
+    /// Only entries at this level or less verbose, e.g. `Warn` matches `Error` and `Warn`.
+    pub max_level: Option<LogLevel>,
+    /// Only entries whose target starts with this prefix.
+    pub target: Option<String>,
+    /// Only entries whose source file contains this string.
+    pub file: Option<String>,
+}
+
+impl LogFilter {
+    fn matches(&self, entry: &LogEntry) -> bool {
+        self.from_timestamp.map_or(true, |t| entry.timestamp >= t)
+            && self.to_timestamp.map_or(true, |t| entry.timestamp <= t)
+            && self.max_level.map_or(true, |l| entry.level <= l)
+            && self
+                .target
+                .as_ref()
+                .map_or(true, |t| entry.target.starts_with(t.as_str()))
+            && self.file.as_ref().map_or(true, |f| {
+                entry
+                    .file
+                    .as_ref()
+                    .map_or(false, |x| x.contains(f.as_str()))
+            })
     }
+}
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        let buffer = std::mem::take(&mut self.buffer);
-        let json = String::from_utf8(buffer).unwrap();
+/// A page of log entries.
+/// `next` is the cursor to pass as `LogFilter.start` for the following page, or `None` if there are no more entries.
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct LogPage {
+    pub entries: Vec<LogEntry>,
+    pub next: Option<u64>,
+}
+
+/// Collects a page of entries from `entries`, which must yield entries in ascending index order
+/// starting from the cursor of the filter.
+pub(crate) fn page_entries(
+    entries: impl Iterator<Item = LogEntry>,
+    filter: &LogFilter,
+    limit: u64,
+) -> LogPage {
+    let limit = limit.min(MAX_LOG_PAGE_SIZE);
+    let mut page = LogPage {
+        entries: Vec::new(),
+        next: None,
+    };
+    for (scanned, entry) in (0..).zip(entries) {
+        // Timestamps are monotonic, so nothing after this entry can match.
+        if filter.to_timestamp.map_or(false, |t| entry.timestamp > t) {
+            return page;
+        }
+        if page.entries.len() as u64 >= limit || scanned >= MAX_LOG_PAGE_SCAN {
+            page.next = Some(entry.index);
+            return page;
+        }
+        if filter.matches(&entry) {
+            page.entries.push(entry);
+        }
+    }
+    page
+}
+
+fn buffer_page(buffer: &LogBuffer, filter: &LogFilter, limit: u64) -> LogPage {
+    let start = filter.start.unwrap_or(0);
+    page_entries(
+        buffer.iter().filter(|e| e.index >= start).cloned(),
+        filter,
+        limit,
+    )
+}
+
+/// Returns a page of heap log entries matching the filter. Must be called by admins.
+#[query]
+#[modifiers("only_admin")]
+pub fn get_logs(filter: LogFilter, limit: u64) -> LogPage {
+    logs_page(&filter, limit)
+}
+
+/// Returns a page of heap trace entries matching the filter. Must be called by admins.
+#[query]
+#[modifiers("only_admin")]
+pub fn get_traces(filter: LogFilter, limit: u64) -> LogPage {
+    traces_page(&filter, limit)
+}
+
+pub(crate) fn logs_page(filter: &LogFilter, limit: u64) -> LogPage {
+    LOG.with_borrow(|l| buffer_page(l, filter, limit))
+}
 
-        let log_entry = LogEntry {
+pub(crate) fn traces_page(filter: &LogFilter, limit: u64) -> LogPage {
+    TRACE.with_borrow(|t| buffer_page(t, filter, limit))
+}
+
+// Allocates the next sequence index of the log or trace buffer.
+// The indices are kept in the heap, so that logging does not write to stable memory.
+fn next_index(trace: bool) -> u64 {
+    let counter = if trace { &TRACE_INDEX } else { &LOG_INDEX };
+    counter.with(|c| c.replace(c.get() + 1))
+}
+
+// Restores the sequence indices persisted before the last upgrade.
+fn restore_indices() {
+    #[allow(clippy::unwrap_used)] // unwrap desired
+    let flags = GLOBAL_FLAGS.with(|f| f.borrow().get().0.clone().unwrap());
+    LOG_INDEX.with(|c| c.set(flags.log_index));
+    TRACE_INDEX.with(|c| c.set(flags.trace_index));
+}
+
+/// Persists the state of the logger that is kept in the heap. Called by `rustic_pre_upgrade`.
+pub(crate) fn pre_upgrade() {
+    GLOBAL_FLAGS.with(|f| {
+        let mut f = f.borrow_mut();
+        #[allow(clippy::unwrap_used)] // unwrap desired
+        let mut flags = f.get().0.clone().unwrap();
+        flags.log_index = LOG_INDEX.with(|c| c.get());
+        flags.trace_index = TRACE_INDEX.with(|c| c.get());
+        #[cfg(feature = "stable-logging")]
+        {
+            flags.stable_log_dropped = crate::logging_stable::stable_log_dropped();
+        }
+        #[allow(clippy::expect_used)] // unwrap desired
+        f.set(Cbor(Some(flags))).expect("Log index update failed");
+    });
+    LOG.with_borrow(|l| LOG_UPGRADE_BUFFER.with(|b| preserve_entries(b, l)));
+    TRACE.with_borrow(|t| TRACE_UPGRADE_BUFFER.with(|b| preserve_entries(b, t)));
+}
+
+pub(crate) fn get_config() -> LoggingConfig {
+    #[allow(clippy::unwrap_used)] // unwrap desired
+    LOGGING_CONFIG.with(|c| c.borrow().get().0.clone().unwrap())
+}
+
+pub(crate) fn update_config(f: impl FnOnce(&mut LoggingConfig)) {
+    LOGGING_CONFIG.with(|c| {
+        let mut c = c.borrow_mut();
+        #[allow(clippy::unwrap_used)] // unwrap desired
+        let mut config = c.get().0.clone().unwrap();
+        f(&mut config);
+        #[allow(clippy::expect_used)] // unwrap desired
+        c.set(Cbor(Some(config)))
+            .expect("Logging config update failed");
+    });
+}
+
+/// Returns the current logging configuration. Must be called by admins.
+#[query]
+#[modifiers("only_admin")]
+pub fn get_logging_config() -> LoggingConfig {
+    get_config()
+}
+
+/// Sets the max level recorded by a sink. Must be called by admins.
+#[update]
+#[modifiers("only_admin")]
+pub fn set_log_level(sink: LogSink, level: LogLevel) {
+    unwrap_or_reject(try_set_log_level(sink, level))
+}
+
+/// Same as [`set_log_level`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_set_log_level(sink: LogSink, level: LogLevel) -> Result<(), RusticError> {
+    check_admin()?;
+    update_config(|c| match sink {
+        LogSink::Log => c.log_level = level,
+        LogSink::Trace => c.trace_level = level,
+        #[cfg(feature = "stable-logging")]
+        LogSink::Stable => c.stable_log_level = level,
+    });
+    Ok(())
+}
+
+/// Resizes the heap log and trace buffers, evicting the oldest entries if needed. Must be called by admins.
+/// A capacity of 0 turns the buffer off.
+#[update]
+#[modifiers("only_admin")]
+pub fn set_log_capacity(log_capacity: u64, trace_capacity: u64) {
+    unwrap_or_reject(try_set_log_capacity(log_capacity, trace_capacity))
+}
+
+/// Same as [`set_log_capacity`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_set_log_capacity(log_capacity: u64, trace_capacity: u64) -> Result<(), RusticError> {
+    check_admin()?;
+    update_config(|c| {
+        c.log_capacity = log_capacity;
+        c.trace_capacity = trace_capacity;
+    });
+    LOG.with_borrow_mut(|l| l.set_max_capacity(log_capacity as usize));
+    TRACE.with_borrow_mut(|t| t.set_max_capacity(trace_capacity as usize));
+    Ok(())
+}
+
+/// Turns recording into the heap trace buffer on or off. Must be called by admins.
+#[update]
+#[modifiers("only_admin")]
+pub fn set_trace_enabled(enabled: bool) {
+    unwrap_or_reject(try_set_trace_enabled(enabled))
+}
+
+/// Same as [`set_trace_enabled`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_set_trace_enabled(enabled: bool) -> Result<(), RusticError> {
+    check_admin()?;
+    update_config(|c| c.trace_enabled = enabled);
+    Ok(())
+}
+
+/// Destination of log entries.
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub enum LogSink {
+    Log,
+    Trace,
+    #[cfg(feature = "stable-logging")]
+    Stable,
+}
+
+impl LogSink {
+    // Whether the sink currently records entries of the given level.
+    fn enabled(self, level: &Level) -> bool {
+        let level = LogLevel::from(level);
+        LOGGING_CONFIG.with(|c| {
+            let c = c.borrow();
+            #[allow(clippy::unwrap_used)] // unwrap desired
+            let config = c.get().0.as_ref().unwrap();
+            match self {
+                LogSink::Log => level <= config.log_level,
+                LogSink::Trace => config.trace_enabled && level <= config.trace_level,
+                #[cfg(feature = "stable-logging")]
+                LogSink::Stable => level <= config.stable_log_level,
+            }
+        })
+    }
+
+    fn append(self, mut entry: LogEntry) {
+        match self {
+            LogSink::Log => {
+                entry.index = next_index(false);
+                LOG.with_borrow_mut(|l| l.append(entry));
+            }
+            LogSink::Trace => {
+                entry.index = next_index(true);
+                TRACE.with_borrow_mut(|t| t.append(entry));
+            }
+            #[cfg(feature = "stable-logging")]
+            LogSink::Stable => crate::logging_stable::stable_log_append(entry),
+        }
+    }
+}
+
+/// `tracing` layer recording events as [`LogEntry`]s into a sink, according to the [`LoggingConfig`].
+/// The trace sink also records every time a span is entered.
+pub(crate) struct LogLayer {
+    sink: LogSink,
+}
+
+impl LogLayer {
+    pub(crate) fn new(sink: LogSink) -> Self {
+        Self { sink }
+    }
+
+    fn entry(&self, meta: &Metadata<'_>, span: Option<String>, visitor: FieldVisitor) -> LogEntry {
+        LogEntry {
+            index: 0,
             timestamp: canister_time(),
-            message: json,
-        };
+            level: meta.level().into(),
+            target: meta.target().to_string(),
+            file: meta.file().map(|f| f.to_string()),
+            line: meta.line(),
+            span,
+            message: visitor.message,
+            fields: visitor.fields,
+        }
+    }
+}
+
+// Fields recorded when a span is created, stored in the span extensions.
+struct SpanFields(Vec<(String, String)>);
+
+impl<S> Layer<S> for LogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !matches!(self.sink, LogSink::Trace) {
+            return;
+        }
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(visitor.fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        if !self.sink.enabled(meta.level()) {
+            return;
+        }
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let span = ctx.event_span(event).map(|s| s.name().to_string());
+        self.sink.append(self.entry(meta, span, visitor));
+    }
 
-        let sink = if self.trace { &TRACE } else { &LOG };
-        sink.with_borrow_mut(|s| s.append(log_entry));
-        Ok(())
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if !matches!(self.sink, LogSink::Trace) {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            let meta = span.metadata();
+            if !self.sink.enabled(meta.level()) {
+                return;
+            }
+            let visitor = FieldVisitor {
+                message: "enter".to_string(),
+                fields: span
+                    .extensions()
+                    .get::<SpanFields>()
+                    .map(|f| f.0.clone())
+                    .unwrap_or_default(),
+            };
+            self.sink
+                .append(self.entry(meta, Some(span.name().to_string()), visitor));
+        }
+    }
+}
+
+// Collects the `message` field and all other fields as key/value pairs.
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: Vec<(String, String)>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields
+                .push((field.name().to_string(), value.to_string()));
+        }
     }
 
-    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
-        self.write(buf).and_then(|_| self.flush())
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let mut formatted = String::new();
+        let _ = write!(formatted, "{:?}", value);
+        if field.name() == "message" {
+            self.message = formatted;
+        } else {
+            self.fields.push((field.name().to_string(), formatted));
+        }
     }
 }
 
-struct Timer;
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use candid::Principal;
+
+    fn entry(index: u64, timestamp: u64, level: LogLevel, target: &str) -> LogEntry {
+        LogEntry {
+            index,
+            timestamp,
+            level,
+            target: target.to_string(),
+            file: Some(format!("src/{target}.rs")),
+            line: None,
+            span: None,
+            message: format!("message {index}"),
+            fields: vec![],
+        }
+    }
+
+    fn fill_log() {
+        LOG.with_borrow_mut(|l| {
+            *l = LogBuffer::with_capacity(4);
+            for i in 0..6 {
+                let level = if i % 2 == 0 {
+                    LogLevel::Info
+                } else {
+                    LogLevel::Error
+                };
+                let target = if i < 3 { "app::a" } else { "app::b" };
+                l.append(entry(i, 100 + i, level, target));
+            }
+        });
+    }
+
+    fn indices(page: &LogPage) -> Vec<u64> {
+        page.entries.iter().map(|e| e.index).collect()
+    }
+
+    #[test]
+    fn test_get_logs_pagination() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        fill_log();
+
+        // Entries 0 and 1 were evicted.
+        let page = get_logs(LogFilter::default(), 3);
+        assert_eq!(indices(&page), vec![2, 3, 4]);
+        assert_eq!(page.next, Some(5));
+
+        let page = get_logs(
+            LogFilter {
+                start: page.next,
+                ..Default::default()
+            },
+            3,
+        );
+        assert_eq!(indices(&page), vec![5]);
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn test_get_logs_filter() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        fill_log();
+
+        let page = get_logs(
+            LogFilter {
+                max_level: Some(LogLevel::Warn),
+                ..Default::default()
+            },
+            10,
+        );
+        assert_eq!(indices(&page), vec![3, 5]);
+
+        let page = get_logs(
+            LogFilter {
+                target: Some("app::a".to_string()),
+                ..Default::default()
+            },
+            10,
+        );
+        assert_eq!(indices(&page), vec![2]);
+
+        let page = get_logs(
+            LogFilter {
+                file: Some("b.rs".to_string()),
+                from_timestamp: Some(104),
+                ..Default::default()
+            },
+            10,
+        );
+        assert_eq!(indices(&page), vec![4, 5]);
+
+        let page = get_logs(
+            LogFilter {
+                to_timestamp: Some(103),
+                ..Default::default()
+            },
+            1,
+        );
+        assert_eq!(indices(&page), vec![2]);
+        assert_eq!(page.next, Some(3));
+    }
+
+    #[test]
+    fn test_log_layer() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_mock_time(42);
+
+        let subscriber = Registry::default()
+            .with(LogLayer::new(LogSink::Log))
+            .with(LogLayer::new(LogSink::Trace));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("transfer", amount = 5);
+            let _enter = span.enter();
+            tracing::debug!("trace only");
+            tracing::warn!(to = "alice", ok = true, "done");
+        });
+
+        let logs = export_logs();
+        assert_eq!(logs.len(), 1);
+        let log = &logs[0];
+        assert_eq!(log.timestamp, 42);
+        assert!(log.level == LogLevel::Warn);
+        assert_eq!(log.target, module_path!());
+        assert_eq!(log.file.as_deref(), Some(file!()));
+        assert!(log.line.is_some());
+        assert_eq!(log.span.as_deref(), Some("transfer"));
+        assert_eq!(log.message, "done");
+        assert_eq!(
+            log.fields,
+            vec![
+                ("to".to_string(), "alice".to_string()),
+                ("ok".to_string(), "true".to_string())
+            ]
+        );
+
+        let traces = export_traces();
+        assert_eq!(traces.len(), 3);
+        assert_eq!(traces[0].message, "enter");
+        assert_eq!(
+            traces[0].fields,
+            vec![("amount".to_string(), "5".to_string())]
+        );
+        assert_eq!(traces[1].message, "trace only");
+        assert_eq!(
+            traces.iter().map(|t| t.index).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+    }
 
-impl FormatTime for Timer {
-    fn format_time(&self, w: &mut Writer) -> std::fmt::Result {
-        let now = canister_time();
+    #[test]
+    fn test_logging_config() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        fill_log();
+
+        set_log_level(LogSink::Log, LogLevel::Error);
+        set_log_level(LogSink::Trace, LogLevel::Info);
+        set_trace_enabled(false);
+        set_log_capacity(2, 50);
+        let config = get_logging_config();
+        assert!(config.log_level == LogLevel::Error);
+        assert!(config.trace_level == LogLevel::Info);
+        assert!(!config.trace_enabled);
+        assert_eq!(config.log_capacity, 2);
+        assert_eq!(config.trace_capacity, 50);
+        assert_eq!(
+            export_logs().iter().map(|e| e.index).collect::<Vec<_>>(),
+            vec![4, 5]
+        );
+
+        let subscriber = Registry::default()
+            .with(LogLayer::new(LogSink::Log))
+            .with(LogLayer::new(LogSink::Trace));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!("filtered");
+            tracing::error!("recorded");
+        });
+        let logs = export_logs();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[1].message, "recorded");
+        assert!(export_traces().is_empty());
+
+        set_trace_enabled(true);
+        tracing::subscriber::with_default(
+            Registry::default().with(LogLayer::new(LogSink::Trace)),
+            || {
+                tracing::debug!("filtered");
+                tracing::info!("recorded");
+            },
+        );
+        assert_eq!(export_traces().len(), 1);
+
+        set_log_capacity(0, 0);
+        assert!(export_logs().is_empty());
+        tracing::subscriber::with_default(
+            Registry::default().with(LogLayer::new(LogSink::Log)),
+            || tracing::error!("dropped"),
+        );
+        assert!(export_logs().is_empty());
+
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        assert_eq!(
+            try_set_log_level(LogSink::Log, LogLevel::Debug),
+            Err(RusticError::NotAdmin)
+        );
+        assert_eq!(try_set_log_capacity(10, 10), Err(RusticError::NotAdmin));
+        assert_eq!(try_set_trace_enabled(false), Err(RusticError::NotAdmin));
+        let config = get_config();
+        assert!(config.log_level == LogLevel::Error);
+        assert_eq!(config.log_capacity, 0);
+        assert!(config.trace_enabled);
+    }
+
+    #[test]
+    fn test_preserve_logs_across_upgrade() {
+        let subscriber = Registry::default()
+            .with(LogLayer::new(LogSink::Log))
+            .with(LogLayer::new(LogSink::Trace));
+        tracing::subscriber::with_default(subscriber, || {
+            for i in 0..PRESERVED_LOG_ENTRIES + 5 {
+                tracing::info!("line {i}");
+            }
+        });
+        assert_eq!(export_logs().len(), DEFAULT_LOG_CAPACITY as usize);
+        assert!(LOG_UPGRADE_BUFFER.with_borrow(|b| b.is_empty()));
+        pre_upgrade();
+
+        // The heap buffers are wiped during upgrades.
+        LOG.with_borrow_mut(|l| *l = LogBuffer::default());
+        TRACE.with_borrow_mut(|t| *t = LogBuffer::default());
+        restore_preserved_entries();
+
+        let logs = export_logs();
+        assert_eq!(logs.len(), PRESERVED_LOG_ENTRIES as usize);
+        assert_eq!(logs[0].index, 5);
+        assert_eq!(logs[0].message, "line 5");
+        assert_eq!(export_traces().len(), PRESERVED_LOG_ENTRIES as usize);
+    }
+
+    #[test]
+    fn test_indices_across_upgrade() {
+        let subscriber = Registry::default().with(LogLayer::new(LogSink::Log));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("first");
+            tracing::info!("second");
+        });
+        pre_upgrade();
+
+        // The heap is wiped during upgrades.
+        LOG_INDEX.with(|c| c.set(0));
+        LOG.with_borrow_mut(|l| *l = LogBuffer::default());
+        restore_indices();
+        let subscriber = Registry::default().with(LogLayer::new(LogSink::Log));
+        tracing::subscriber::with_default(subscriber, || tracing::info!("third"));
+        assert_eq!(export_logs()[0].index, 2);
+    }
+
+    #[test]
+    fn test_panic_guard() {
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        let subscriber = Registry::default().with(LogLayer::new(LogSink::Log));
+        tracing::subscriber::with_default(subscriber, || {
+            {
+                let _guard = PanicGuard::new("outer");
+                {
+                    let _guard = PanicGuard::new("inner");
+                    assert_eq!(
+                        PANIC_CONTEXT.with(|c| c.borrow().clone()).unwrap().0,
+                        "inner"
+                    );
+                }
+                assert_eq!(
+                    PANIC_CONTEXT.with(|c| c.borrow().clone()).unwrap().0,
+                    "outer"
+                );
+            }
+            assert!(PANIC_CONTEXT.with(|c| c.borrow().is_none()));
+            assert!(export_logs().is_empty());
+
+            set_mock_recovering_from_trap(true);
+            drop(PanicGuard::new("transfer"));
+            set_mock_recovering_from_trap(false);
+        });
+
+        let logs = export_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].level == LogLevel::Error);
+        assert_eq!(logs[0].target, "rustic::panic");
+        assert_eq!(
+            logs[0].fields,
+            vec![
+                ("method".to_string(), "transfer".to_string()),
+                ("caller".to_string(), MOCK_USER_1.to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_trap() {
+        let caller = Principal::from_text(MOCK_USER_1).unwrap();
+        let subscriber = Registry::default().with(LogLayer::new(LogSink::Log));
+        tracing::subscriber::with_default(subscriber, || {
+            record_trap("transfer", caller, &Ok(vec![]));
+            record_trap(
+                "transfer",
+                caller,
+                &Err((RejectionCode::SysTransient, "busy".to_string())),
+            );
+            record_trap(
+                "transfer",
+                caller,
+                &Err((
+                    RejectionCode::CanisterError,
+                    "Panicked at 'overflow', src/lib.rs:1:1".to_string(),
+                )),
+            );
+        });
+
+        let logs = export_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].level == LogLevel::Error);
+        assert_eq!(logs[0].target, "rustic::panic");
+        assert_eq!(logs[0].message, "Panicked at 'overflow', src/lib.rs:1:1");
+        assert_eq!(
+            logs[0].fields,
+            vec![
+                ("method".to_string(), "transfer".to_string()),
+                ("caller".to_string(), MOCK_USER_1.to_string())
+            ]
+        );
+    }
 
-        w.write_str(&format!("{now}"))
+    #[test]
+    #[should_panic(expected = "msg_reject should only be called inside canisters")]
+    fn test_get_logs_unauth() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        get_logs(LogFilter::default(), 10);
     }
 }
@@ -5,83 +5,196 @@
 
 //! # Stable Logging Module for Canisters
 //! Logs are stored in stable memory, which is persisted across canister upgrades.
+//!
+//! The stable log is an append-only `ic_stable_structures::Log`.
+//! It is installed as an additional `tracing` layer next to the heap logger,
+//! so the `stable-logging` feature implies the `logging` feature.
+//! The index of a stable log entry is its position in the log.
+//!
+//! The stable log cannot evict entries, so once it reaches the capacity set with [`set_stable_log_capacity`],
+//! or the stable memory is exhausted, new entries are dropped.
+//! The number of dropped entries is returned by [`get_stable_log_dropped`].
 
+use crate::access_control::*;
+use crate::error::*;
+use crate::global_flags::*;
+use crate::logging::*;
 use crate::memory_map::*;
 #[cfg(test)]
 use crate::testing::*;
 use crate::types::*;
-use crate::utils::*;
 
-use candid::CandidType;
-use ic_stable_structures::{storable::Blob, Log, Storable};
-use serde::{Deserialize, Serialize};
-use std::borrow::Cow;
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::Log;
+use rustic_macros::modifiers;
 use std::cell::{Cell, RefCell};
-use std::collections::VecDeque;
-use std::io::Write;
-use tracing::Level;
-
-// thread_local! {
-//     pub static STABLE_LOG: RefCell<Log<StableLogEntry, VM, VM>> =
-//         MEMORY_MANAGER.with(|mm| {
-//             RefCell::new(Log::new(mm.borrow().get(STABLE_LOG_IDX_ID), mm.borrow().get(STABLE_LOG_MEM_ID)))
-//     });
-// }
-
-// #[derive(CandidType, Serialize, Deserialize, Clone)]
-// pub struct StableLogEntry {
-//     pub timestamp: u64,
-//     pub level: Level,
-//     pub message: String,
-// }
-
-// impl Storable for StableLogEntry {
-//     fn to_bytes(&self) -> Cow<[u8]> {
-//         let mut bytes = Vec::new();
-//         bytes.extend_from_slice(&self.timestamp.to_le_bytes());
-//         bytes.push(self.message.len() as u8);
-//         bytes.extend_from_slice(self.message.as_bytes());
-//         bytes.into()
-//     }
-
-//     /// Panics if the bytes are invalid.
-//     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-//         assert!(bytes.len() >= 8 + 1);
-//         #[allow(clippy::unwrap_used)] // unwrap allowed
-//         let timestamp = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
-//         let message_length = bytes[8] as usize;
-//         let message_bytes = &bytes[9..9 + message_length];
-//         let message = String::from_utf8_lossy(message_bytes).to_string();
-//         Self { timestamp, message }
-//     }
-
-//     const BOUND: ic_stable_structures::storable::Bound =
-//         ic_stable_structures::storable::Bound::Unbounded;
-// }
-
-// impl std::fmt::Display for StableLogEntry {
-//     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-//         write!(f, "{} {:?}", self.timestamp, self.message)
-//     }
-// }
-
-// /// Add a line of given log level to the debug log, only when
-// /// the given level is smaller than or equal to config.debug_log_level.
-// pub fn stable_log(level: Level, line: String) -> Result<(), ReturnError> {
-//     let config = get_mpc_config();
-//     if (level as u8) <= config.debug_log_level {
-//         STABLE_LOG.with(|log| {
-//             log.borrow()
-//                 .append(&format!(
-//                     "{} {:?} {}",
-//                     canister_time() / 1_000_000,
-//                     level,
-//                     line
-//                 ))
-//                 .map(|_| ())
-//                 .map_err(|_| ReturnError::OutOfMemory)
-//         })
-//     } else {
-//         Ok(())
-//     }
-// }
+
+thread_local! {
+    // Number of entries dropped, persisted in the pre-upgrade hook.
+    static DROPPED: Cell<u64> = Cell::default();
+
+    static STABLE_LOG: RefCell<Log<Cbor<LogEntry>, VM, VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            #[allow(clippy::expect_used)] // safe unwrap during init
+            RefCell::new(Log::init(
+                mm.borrow().get(STABLE_LOG_IDX_ID),
+                mm.borrow().get(STABLE_LOG_MEM_ID),
+            ).expect("Failed to initialize the stable log"))
+    });
+}
+
+pub(crate) fn stable_log_init() {
+    STABLE_LOG.with(|l| {
+        l.borrow();
+    });
+    #[allow(clippy::unwrap_used)] // unwrap desired
+    let dropped = GLOBAL_FLAGS.with(|f| f.borrow().get().0.clone().unwrap().stable_log_dropped);
+    DROPPED.with(|d| d.set(dropped));
+}
+
+/// Appends an entry to the stable log, overwriting its index with the position in the log.
+/// Entries beyond the capacity, or that cannot be written (e.g. stable memory is exhausted), are dropped and counted.
+pub(crate) fn stable_log_append(mut entry: LogEntry) {
+    let capacity = get_config().stable_log_capacity;
+    let appended = STABLE_LOG.with(|l| {
+        let l = l.borrow();
+        entry.index = l.len();
+        l.len() < capacity && l.append(&Cbor(entry)).is_ok()
+    });
+    if !appended {
+        DROPPED.with(|d| d.set(d.get() + 1));
+    }
+}
+
+/// Returns the number of entries dropped because the stable log was full. Must be called by admins.
+#[query]
+#[modifiers("only_admin")]
+pub fn get_stable_log_dropped() -> u64 {
+    stable_log_dropped()
+}
+
+pub(crate) fn stable_log_dropped() -> u64 {
+    DROPPED.with(|d| d.get())
+}
+
+/// Sets the max number of entries of the stable log. Must be called by admins.
+/// Lowering the capacity below the current length does not remove entries, but stops appending.
+#[update]
+#[modifiers("only_admin")]
+pub fn set_stable_log_capacity(capacity: u64) {
+    unwrap_or_reject(try_set_stable_log_capacity(capacity))
+}
+
+/// Same as [`set_stable_log_capacity`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_set_stable_log_capacity(capacity: u64) -> Result<(), RusticError> {
+    check_admin()?;
+    update_config(|c| c.stable_log_capacity = capacity);
+    Ok(())
+}
+
+/// Returns the number of entries in the stable log. Must be called by admins.
+#[query]
+#[modifiers("only_admin")]
+pub fn get_stable_log_len() -> u64 {
+    STABLE_LOG.with(|l| l.borrow().len())
+}
+
+/// Returns a page of stable log entries matching the filter. Must be called by admins.
+#[query]
+#[modifiers("only_admin")]
+pub fn get_stable_logs(filter: LogFilter, limit: u64) -> LogPage {
+    stable_logs_page(&filter, limit)
+}
+
+pub(crate) fn stable_logs_page(filter: &LogFilter, limit: u64) -> LogPage {
+    STABLE_LOG.with(|l| {
+        let l = l.borrow();
+        let start = filter.start.unwrap_or(0);
+        page_entries(
+            (start..l.len()).filter_map(|i| l.get(i).map(|e| e.0)),
+            filter,
+            limit,
+        )
+    })
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::utils::canister_caller;
+    use candid::Principal;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    #[test]
+    fn test_stable_log_layer() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_mock_time(42);
+        stable_log_init();
+        assert_eq!(get_stable_log_len(), 0);
+
+        let subscriber = Registry::default().with(LogLayer::new(LogSink::Stable));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("filtered");
+            tracing::info!("first");
+            tracing::warn!(answer = 42, "second");
+        });
+
+        assert_eq!(get_stable_log_len(), 2);
+        let page = get_stable_logs(LogFilter::default(), 1);
+        assert_eq!(page.entries.len(), 1);
+        assert_eq!(page.entries[0].index, 0);
+        assert_eq!(page.entries[0].timestamp, 42);
+        assert_eq!(page.entries[0].message, "first");
+        assert_eq!(page.next, Some(1));
+
+        let page = get_stable_logs(
+            LogFilter {
+                start: page.next,
+                ..Default::default()
+            },
+            10,
+        );
+        assert_eq!(page.entries.len(), 1);
+        assert_eq!(page.entries[0].index, 1);
+        assert!(page.entries[0].level == LogLevel::Warn);
+        assert_eq!(page.entries[0].message, "second");
+        assert_eq!(
+            page.entries[0].fields,
+            vec![("answer".to_string(), "42".to_string())]
+        );
+        assert_eq!(page.next, None);
+
+        let page = get_stable_logs(
+            LogFilter {
+                max_level: Some(LogLevel::Warn),
+                ..Default::default()
+            },
+            10,
+        );
+        assert_eq!(page.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_stable_log_capacity() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        stable_log_init();
+        set_stable_log_capacity(2);
+
+        let subscriber = Registry::default().with(LogLayer::new(LogSink::Stable));
+        tracing::subscriber::with_default(subscriber, || {
+            for i in 0..5 {
+                tracing::info!("line {i}");
+            }
+        });
+        assert_eq!(get_stable_log_len(), 2);
+        assert_eq!(get_stable_log_dropped(), 3);
+        assert_eq!(get_logging_config().stable_log_capacity, 2);
+
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        assert_eq!(try_set_stable_log_capacity(10), Err(RusticError::NotAdmin));
+    }
+}
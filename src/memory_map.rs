@@ -12,6 +12,8 @@ include!(concat!(env!("OUT_DIR"), "/config.rs"));
 const GLOBAL_FLAGS_PAGE_SIZE: u64 = 1;
 const CANISTER_LIFECYCLE_PAGE_SIZE: u64 = 1;
 const ACCESS_CONTROL_PAGE_SIZE: u64 = 4;
+const LOGGING_CONFIG_PAGE_SIZE: u64 = 1;
+const MULTISIG_CONFIG_PAGE_SIZE: u64 = 1;
 
 pub(crate) const GLOBAL_FLAGS_PAGE_START: u64 = 0;
 pub(crate) const GLOBAL_FLAGS_PAGE_END: u64 = GLOBAL_FLAGS_PAGE_START + GLOBAL_FLAGS_PAGE_SIZE;
@@ -21,6 +23,16 @@ pub(crate) const CANISTER_LIFECYCLE_PAGE_END: u64 =
 pub(crate) const ACCESS_CONTROL_PAGE_START: u64 = CANISTER_LIFECYCLE_PAGE_END;
 pub(crate) const ACCESS_CONTROL_PAGE_END: u64 =
     ACCESS_CONTROL_PAGE_START + ACCESS_CONTROL_PAGE_SIZE;
+#[allow(unused)]
+pub(crate) const LOGGING_CONFIG_PAGE_START: u64 = ACCESS_CONTROL_PAGE_END;
+#[allow(unused)]
+pub(crate) const LOGGING_CONFIG_PAGE_END: u64 =
+    LOGGING_CONFIG_PAGE_START + LOGGING_CONFIG_PAGE_SIZE;
+#[allow(unused)]
+pub(crate) const MULTISIG_CONFIG_PAGE_START: u64 = LOGGING_CONFIG_PAGE_END;
+#[allow(unused)]
+pub(crate) const MULTISIG_CONFIG_PAGE_END: u64 =
+    MULTISIG_CONFIG_PAGE_START + MULTISIG_CONFIG_PAGE_SIZE;
 
 // Define user page range
 pub const USER_PAGE_START: u64 = 64;
@@ -34,8 +46,47 @@ pub(crate) const REENTRANCY_GUARD_MEM_ID: MemoryId = MemoryId::new(224);
 pub(crate) const STABLE_LOG_IDX_ID: MemoryId = MemoryId::new(226);
 #[allow(unused)]
 pub(crate) const STABLE_LOG_MEM_ID: MemoryId = MemoryId::new(227);
-//pub(crate) const UPGRADE_BUFFER_MEM_ID: MemoryId = MemoryId::new(228);
-pub(crate) const ACCESS_ROLES_MEM_ID: MemoryId = MemoryId::new(229);
+#[allow(unused)]
+pub(crate) const LOG_UPGRADE_BUFFER_MEM_ID: MemoryId = MemoryId::new(228);
+#[allow(unused)]
+pub(crate) const LEGACY_ACCESS_ROLES_MEM_ID: MemoryId = MemoryId::new(229);
+#[allow(unused)]
+pub(crate) const TRACE_UPGRADE_BUFFER_MEM_ID: MemoryId = MemoryId::new(230);
+#[allow(unused)]
+pub(crate) const INSPECTION_MEM_ID: MemoryId = MemoryId::new(231);
+#[allow(unused)]
+pub(crate) const AUDIT_LOG_IDX_ID: MemoryId = MemoryId::new(232);
+#[allow(unused)]
+pub(crate) const AUDIT_LOG_MEM_ID: MemoryId = MemoryId::new(233);
+#[allow(unused)]
+pub(crate) const ROLE_MEMBERS_MEM_ID: MemoryId = MemoryId::new(234);
+#[allow(unused)]
+pub(crate) const ROLE_MEMBER_COUNTS_MEM_ID: MemoryId = MemoryId::new(235);
+#[allow(unused)]
+pub(crate) const ROLE_REGISTRY_MEM_ID: MemoryId = MemoryId::new(236);
+#[allow(unused)]
+pub(crate) const ACCESS_ROLES_MEM_ID: MemoryId = MemoryId::new(237);
+#[allow(unused)]
+pub(crate) const ROLE_ADMINS_MEM_ID: MemoryId = MemoryId::new(238);
+#[allow(unused)]
+pub(crate) const ROLE_EXPIRIES_MEM_ID: MemoryId = MemoryId::new(239);
+#[allow(unused)]
+pub(crate) const MULTISIG_PROPOSALS_MEM_ID: MemoryId = MemoryId::new(240);
+#[allow(unused)]
+pub(crate) const ADMINS_MEM_ID: MemoryId = MemoryId::new(241);
+#[allow(unused)]
+pub(crate) const ROLE_DELEGATIONS_MEM_ID: MemoryId = MemoryId::new(242);
+#[allow(unused)]
+pub(crate) const ROLE_DELEGATORS_MEM_ID: MemoryId = MemoryId::new(243);
+#[allow(unused)]
+pub(crate) const PERMISSIONS_MEM_ID: MemoryId = MemoryId::new(244);
+
+#[allow(unused)]
+pub(crate) const DENYLIST_MEM_ID: MemoryId = MemoryId::new(245);
+#[allow(unused)]
+pub(crate) const ALLOWLIST_MEM_ID: MemoryId = MemoryId::new(246);
+#[allow(unused)]
+pub(crate) const ROLE_EXPIRY_QUEUE_MEM_ID: MemoryId = MemoryId::new(247);
 
 thread_local! {
     // This would be automatically initialized on the first access of anything using the MemoryManager.
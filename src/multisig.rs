@@ -0,0 +1,521 @@
+#![cfg(feature = "multisig")]
+
+//! M-of-N admin approval of privileged operations.
+//!
+//! Multisig is disabled by default, so that privileged operations are performed directly by a single admin or the owner.
+//! The owner enables it with [`set_multisig_config`] by setting a `threshold` of at least 2 admin approvals.
+//! Once enabled, the following operations can only be performed through proposals:
+//! - granting and revoking admins,
+//! - setting and revoking role admins (with the `access-roles` feature),
+//! - pausing and resuming (with the `pausable` feature),
+//! - changing the multisig configuration, including disabling it by setting the threshold to 1.
+//!
+//! Any admin can create a proposal with [`propose`], which counts as the first approval.
+//! Other admins approve it with [`approve_proposal`], and the proposal is executed
+//! as part of the approval that reaches the threshold.
+//! Only approvals of principals that are still admins are counted.
+//! Proposals that are not executed before their expiry can no longer be approved.
+//! Executed, cancelled and expired proposals are kept until an admin removes them with [`prune_proposals`].
+//!
+//! The configuration is kept in stable memory, and proposals in a stable map, so both survive upgrades.
+//! With the `audit-events` feature, creating, approving and cancelling proposals are recorded in the audit log,
+//! next to the events of the executed operations.
+
+use crate::access_control::*;
+#[cfg(feature = "audit-events")]
+use crate::audit::*;
+use crate::error::*;
+use crate::memory_map::*;
+#[cfg(test)]
+use crate::testing::*;
+use crate::types::*;
+use crate::utils::*;
+use candid::{CandidType, Principal};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use rustic_macros::modifiers;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+/// Default time to live of a proposal in nanoseconds (7 days).
+pub const DEFAULT_PROPOSAL_TTL: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+/// Maximum number of proposals returned in a single page.
+pub const MAX_PROPOSAL_PAGE_SIZE: u64 = 100;
+
+/// Multisig configuration, persisted in stable memory.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub struct MultisigConfig {
+    /// Number of admin approvals required to execute a proposal. Multisig is disabled if this is at most 1.
+    pub threshold: u32,
+    /// Time to live of new proposals in nanoseconds.
+    pub proposal_ttl: u64,
+}
+
+impl Default for MultisigConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 1,
+            proposal_ttl: DEFAULT_PROPOSAL_TTL,
+        }
+    }
+}
+
+/// A privileged operation that can be proposed.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum ProposalAction {
+    GrantAdmin(Principal),
+    RevokeAdmin(Principal),
+    #[cfg(feature = "access-roles")]
+    SetRoleAdmins {
+        role: u8,
+        admins: Vec<u8>,
+    },
+    #[cfg(feature = "access-roles")]
+    RevokeRoleAdmins {
+        role: u8,
+        admins: Vec<u8>,
+    },
+    #[cfg(feature = "pausable")]
+    Pause,
+    #[cfg(feature = "pausable")]
+    Resume,
+    SetConfig(MultisigConfig),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum ProposalStatus {
+    Pending,
+    Executed,
+    Cancelled,
+}
+
+#[derive(Clone, CandidType, Serialize, Deserialize)]
+pub struct Proposal {
+    pub id: u64,
+    pub action: ProposalAction,
+    pub proposer: Principal,
+    pub created_at: u64,
+    pub expires_at: u64,
+    /// Admins that approved the proposal, in the order of approval.
+    pub approvals: Vec<Principal>,
+    pub status: ProposalStatus,
+}
+
+impl Proposal {
+    fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+}
+
+thread_local! {
+    static MULTISIG_CONFIG: RefCell<StableCell<Cbor<Option<MultisigConfig>>, RM>> =
+        #[allow(clippy::expect_used)] // safe unwrap during init
+        RefCell::new(StableCell::init(
+            RM::new(DefaultMemoryImpl::default(), MULTISIG_CONFIG_PAGE_START..MULTISIG_CONFIG_PAGE_END),
+            Cbor(Some(MultisigConfig::default())),
+        ).expect("Failed to initialize the multisig config cell")
+    );
+    // can be lazily initialized
+    // mapping from proposal id to proposal
+    static PROPOSALS: RefCell<StableBTreeMap<u64, Cbor<Proposal>, VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(MULTISIG_PROPOSALS_MEM_ID)))
+    });
+}
+
+/// Returns the multisig configuration.
+#[query]
+pub fn get_multisig_config() -> MultisigConfig {
+    #[allow(clippy::unwrap_used)] // unwrap desired
+    MULTISIG_CONFIG.with(|c| c.borrow().get().0.clone().unwrap())
+}
+
+fn multisig_enabled() -> bool {
+    get_multisig_config().threshold > 1
+}
+
+/// Guard method for operations that must go through proposals when multisig is enabled.
+/// This is typically used in conjunction with the [`modifiers`] macro.
+pub fn when_multisig_disabled() -> Result<(), String> {
+    Ok(check_multisig_disabled()?)
+}
+
+/// Checks that multisig is disabled, returning a typed error.
+pub fn check_multisig_disabled() -> Result<(), RusticError> {
+    if multisig_enabled() {
+        Err(RusticError::MultisigEnabled)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_config(config: &MultisigConfig) -> Result<(), RusticError> {
+    if config.threshold as u64 > get_admin_count() {
+        Err(RusticError::ThresholdTooHigh)
+    } else {
+        Ok(())
+    }
+}
+
+fn set_config(config: MultisigConfig) {
+    MULTISIG_CONFIG.with(|c| {
+        #[allow(clippy::expect_used)] // unwrap desired
+        c.borrow_mut()
+            .set(Cbor(Some(config)))
+            .expect("Set multisig config failed");
+    });
+}
+
+/// Sets the multisig configuration while multisig is disabled. Must be called by the `owner`.
+/// Once enabled, the configuration can only be changed through a [`ProposalAction::SetConfig`] proposal.
+#[update]
+#[modifiers("only_owner", "when_multisig_disabled")]
+pub fn set_multisig_config(config: MultisigConfig) {
+    unwrap_or_reject(try_set_multisig_config(config))
+}
+
+/// Same as [`set_multisig_config`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_set_multisig_config(config: MultisigConfig) -> Result<(), RusticError> {
+    check_owner()?;
+    check_multisig_disabled()?;
+    check_config(&config)?;
+    set_config(config);
+    Ok(())
+}
+
+// Checks that an action can be executed, so that executing it cannot fail.
+fn check_action(action: &ProposalAction) -> Result<(), RusticError> {
+    match action {
+        ProposalAction::GrantAdmin(p) => check_admin_grantee(*p),
+        ProposalAction::RevokeAdmin(p) => check_admin_removal(*p),
+        #[cfg(feature = "access-roles")]
+        ProposalAction::SetRoleAdmins { .. } | ProposalAction::RevokeRoleAdmins { .. } => Ok(()),
+        #[cfg(feature = "pausable")]
+        ProposalAction::Pause | ProposalAction::Resume => Ok(()),
+        ProposalAction::SetConfig(config) => check_config(config),
+    }
+}
+
+fn execute(action: ProposalAction) {
+    match action {
+        ProposalAction::GrantAdmin(p) => add_admin(p),
+        ProposalAction::RevokeAdmin(p) => remove_admin(p),
+        #[cfg(feature = "access-roles")]
+        ProposalAction::SetRoleAdmins { role, admins } => add_role_admins(role, admins),
+        #[cfg(feature = "access-roles")]
+        ProposalAction::RevokeRoleAdmins { role, admins } => remove_role_admins(role, admins),
+        #[cfg(feature = "pausable")]
+        ProposalAction::Pause => crate::pausable::set_paused(true),
+        #[cfg(feature = "pausable")]
+        ProposalAction::Resume => crate::pausable::set_paused(false),
+        ProposalAction::SetConfig(config) => set_config(config),
+    }
+}
+
+// Records the approval of the caller, and executes the proposal if the threshold is reached.
+fn approve(mut proposal: Proposal) -> Result<ProposalStatus, RusticError> {
+    let caller = canister_caller();
+    if !proposal.approvals.contains(&caller) {
+        proposal.approvals.push(caller);
+    }
+    let approvals = proposal.approvals.iter().filter(|p| is_admin(**p)).count();
+    let executed = approvals >= get_multisig_config().threshold as usize;
+    if executed {
+        check_action(&proposal.action)?;
+    }
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::ProposalApproved(proposal.id), None);
+    if executed {
+        proposal.status = ProposalStatus::Executed;
+        execute(proposal.action.clone());
+    }
+    let status = proposal.status;
+    PROPOSALS.with(|p| p.borrow_mut().insert(proposal.id, Cbor(proposal)));
+    Ok(status)
+}
+
+/// Creates a proposal approved by the caller, and returns its id. Must be called by admins.
+/// The proposal is executed immediately if the caller's approval reaches the threshold.
+#[update]
+#[modifiers("only_admin")]
+pub fn propose(action: ProposalAction) -> u64 {
+    unwrap_or_reject(try_propose(action))
+}
+
+/// Same as [`propose`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_propose(action: ProposalAction) -> Result<u64, RusticError> {
+    check_admin()?;
+    check_action(&action)?;
+    let now = canister_time();
+    let id = PROPOSALS.with(|p| p.borrow().last_key_value().map_or(0, |(k, _)| k + 1));
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::ProposalCreated(id), None);
+    approve(Proposal {
+        id,
+        action,
+        proposer: canister_caller(),
+        created_at: now,
+        expires_at: now.saturating_add(get_multisig_config().proposal_ttl),
+        approvals: vec![],
+        status: ProposalStatus::Pending,
+    })?;
+    Ok(id)
+}
+
+fn pending_proposal(id: u64) -> Result<Proposal, RusticError> {
+    let proposal = PROPOSALS
+        .with(|p| p.borrow().get(&id).map(|x| x.0))
+        .ok_or(RusticError::ProposalNotFound)?;
+    if proposal.status != ProposalStatus::Pending {
+        return Err(RusticError::ProposalNotPending);
+    }
+    if proposal.is_expired(canister_time()) {
+        return Err(RusticError::ProposalExpired);
+    }
+    Ok(proposal)
+}
+
+/// Approves a pending proposal, and returns its status after the approval. Must be called by admins.
+#[update]
+#[modifiers("only_admin")]
+pub fn approve_proposal(id: u64) -> ProposalStatus {
+    unwrap_or_reject(try_approve_proposal(id))
+}
+
+/// Same as [`approve_proposal`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_approve_proposal(id: u64) -> Result<ProposalStatus, RusticError> {
+    check_admin()?;
+    approve(pending_proposal(id)?)
+}
+
+/// Cancels a pending proposal. Must be called by the proposer.
+#[update]
+pub fn cancel_proposal(id: u64) {
+    unwrap_or_reject(try_cancel_proposal(id))
+}
+
+/// Same as [`cancel_proposal`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_cancel_proposal(id: u64) -> Result<(), RusticError> {
+    let mut proposal = pending_proposal(id)?;
+    if proposal.proposer != canister_caller() {
+        return Err(RusticError::NotProposer);
+    }
+    proposal.status = ProposalStatus::Cancelled;
+    PROPOSALS.with(|p| p.borrow_mut().insert(id, Cbor(proposal)));
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::ProposalCancelled(id), None);
+    Ok(())
+}
+
+/// Removes up to `limit` proposals that are executed, cancelled or expired, in ascending order of id,
+/// and returns the number of proposals removed. Must be called by admins.
+/// The most recent proposal is never removed, so that proposal ids are not reused.
+#[update]
+#[modifiers("only_admin")]
+pub fn prune_proposals(limit: u64) -> u64 {
+    unwrap_or_reject(try_prune_proposals(limit))
+}
+
+/// Same as [`prune_proposals`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_prune_proposals(limit: u64) -> Result<u64, RusticError> {
+    check_admin()?;
+    let now = canister_time();
+    PROPOSALS.with(|p| {
+        let mut p = p.borrow_mut();
+        let last = p.last_key_value().map(|(k, _)| k);
+        let prunable: Vec<u64> = p
+            .iter()
+            .filter(|(k, x)| {
+                Some(*k) != last && (x.0.status != ProposalStatus::Pending || x.0.is_expired(now))
+            })
+            .take(limit.min(MAX_PROPOSAL_PAGE_SIZE) as usize)
+            .map(|(k, _)| k)
+            .collect();
+        for id in &prunable {
+            p.remove(id);
+        }
+        Ok(prunable.len() as u64)
+    })
+}
+
+/// Returns a proposal by id.
+#[query]
+pub fn get_proposal(id: u64) -> Option<Proposal> {
+    PROPOSALS.with(|p| p.borrow().get(&id).map(|x| x.0))
+}
+
+/// Returns at most `limit` pending, unexpired proposals with an id of at least `start`, in ascending order of id.
+#[query]
+pub fn get_pending_proposals(start: u64, limit: u64) -> Vec<Proposal> {
+    let now = canister_time();
+    PROPOSALS.with(|p| {
+        p.borrow()
+            .range(start..)
+            .map(|(_, x)| x.0)
+            .filter(|x| x.status == ProposalStatus::Pending && !x.is_expired(now))
+            .take(limit.min(MAX_PROPOSAL_PAGE_SIZE) as usize)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn enable_multisig() -> [Principal; 3] {
+        let users =
+            [MOCK_USER_0, MOCK_USER_1, MOCK_USER_2].map(|u| Principal::from_text(u).unwrap());
+        set_mock_caller(users[0]);
+        set_mock_time(0);
+        access_init(canister_caller());
+        grant_admin(users[1]);
+        grant_admin(users[2]);
+        set_multisig_config(MultisigConfig {
+            threshold: 2,
+            proposal_ttl: 1_000,
+        });
+        users
+    }
+
+    #[test]
+    fn test_multisig_proposal() {
+        let users = enable_multisig();
+        let user3 = Principal::from_text(MOCK_USER_3).unwrap();
+        let id = propose(ProposalAction::GrantAdmin(user3));
+        assert!(!is_admin(user3));
+        let pending = get_pending_proposals(0, 10);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].approvals, vec![users[0]]);
+        assert_eq!(pending[0].expires_at, 1_000);
+
+        // approving twice does not count twice
+        assert_eq!(approve_proposal(id), ProposalStatus::Pending);
+        set_mock_caller(users[1]);
+        assert_eq!(approve_proposal(id), ProposalStatus::Executed);
+        assert!(is_admin(user3));
+        assert!(get_pending_proposals(0, 10).is_empty());
+        assert_eq!(
+            get_proposal(id).unwrap().approvals,
+            vec![users[0], users[1]]
+        );
+
+        // disable multisig through a proposal
+        let id = propose(ProposalAction::SetConfig(MultisigConfig::default()));
+        set_mock_caller(users[2]);
+        approve_proposal(id);
+        assert_eq!(get_multisig_config().threshold, 1);
+        set_mock_caller(users[0]);
+        revoke_admin(user3);
+        assert!(!is_admin(user3));
+    }
+
+    #[test]
+    fn test_multisig_revoked_approver() {
+        let users = enable_multisig();
+        set_mock_caller(users[1]);
+        let id = propose(ProposalAction::RevokeAdmin(users[2]));
+        set_mock_caller(users[0]);
+        let id2 = propose(ProposalAction::RevokeAdmin(users[1]));
+        set_mock_caller(users[2]);
+        approve_proposal(id2);
+        assert!(!is_admin(users[1]));
+        // the approval of users[1] no longer counts
+        set_mock_caller(users[0]);
+        assert_eq!(approve_proposal(id), ProposalStatus::Pending);
+    }
+
+    #[test]
+    fn test_multisig_try_errors() {
+        let users = enable_multisig();
+        assert_eq!(
+            try_revoke_admin(users[1]),
+            Err(RusticError::MultisigEnabled)
+        );
+        assert_eq!(
+            try_propose(ProposalAction::GrantAdmin(Principal::anonymous())),
+            Err(RusticError::AnonymousPrincipal)
+        );
+        assert_eq!(try_approve_proposal(0), Err(RusticError::ProposalNotFound));
+        let id = propose(ProposalAction::SetConfig(MultisigConfig {
+            threshold: 3,
+            proposal_ttl: 1_000,
+        }));
+        set_mock_caller(users[1]);
+        assert_eq!(try_cancel_proposal(id), Err(RusticError::NotProposer));
+        // the threshold is checked again on execution
+        set_mock_caller(users[0]);
+        let id2 = propose(ProposalAction::RevokeAdmin(users[2]));
+        set_mock_caller(users[1]);
+        approve_proposal(id2);
+        assert_eq!(try_approve_proposal(id), Err(RusticError::ThresholdTooHigh));
+        assert_eq!(get_proposal(id).unwrap().approvals, vec![users[0]]);
+        // revoking another admin would leave fewer admins than the threshold
+        assert_eq!(
+            try_propose(ProposalAction::RevokeAdmin(users[0])),
+            Err(RusticError::ThresholdTooHigh)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Proposal has expired")]
+    fn test_multisig_expired() {
+        let users = enable_multisig();
+        let id = propose(ProposalAction::RevokeAdmin(users[2]));
+        set_mock_time(1_000);
+        assert!(get_pending_proposals(0, 10).is_empty());
+        set_mock_caller(users[1]);
+        approve_proposal(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Proposal is not pending")]
+    fn test_multisig_cancel() {
+        let users = enable_multisig();
+        let id = propose(ProposalAction::RevokeAdmin(users[2]));
+        cancel_proposal(id);
+        set_mock_caller(users[1]);
+        approve_proposal(id);
+    }
+
+    #[test]
+    fn test_multisig_prune() {
+        let users = enable_multisig();
+        let executed = propose(ProposalAction::RevokeAdmin(users[2]));
+        set_mock_caller(users[1]);
+        approve_proposal(executed);
+        let cancelled = propose(ProposalAction::GrantAdmin(users[2]));
+        cancel_proposal(cancelled);
+        let expired = propose(ProposalAction::GrantAdmin(users[2]));
+        set_mock_time(1_000);
+        let pending = propose(ProposalAction::GrantAdmin(users[2]));
+        let last = propose(ProposalAction::GrantAdmin(users[2]));
+        cancel_proposal(last);
+
+        assert_eq!(prune_proposals(1), 1);
+        assert!(get_proposal(executed).is_none());
+        assert_eq!(prune_proposals(10), 2);
+        assert!(get_proposal(cancelled).is_none());
+        assert!(get_proposal(expired).is_none());
+        // pending proposals and the most recent proposal are kept
+        assert!(get_proposal(pending).is_some());
+        assert!(get_proposal(last).is_some());
+        assert_eq!(prune_proposals(10), 0);
+        assert_eq!(propose(ProposalAction::GrantAdmin(users[2])), last + 1);
+
+        set_mock_caller(Principal::from_text(MOCK_USER_3).unwrap());
+        assert_eq!(try_prune_proposals(10), Err(RusticError::NotAdmin));
+    }
+
+    #[test]
+    #[should_panic(expected = "msg_reject should only be called inside canisters")]
+    fn test_multisig_direct_call() {
+        let users = enable_multisig();
+        revoke_admin(users[1]);
+    }
+}
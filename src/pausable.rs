@@ -9,9 +9,16 @@
 //! need to be disabled whilst other functions can still be called normally.
 //!
 //! By default only `admins` can pause/resume.
+//! With the `multisig` feature, pausing and resuming can be restricted to M-of-N admin approved proposals.
+//! With the `audit-events` feature, pausing and resuming are recorded in the audit log.
 
 use crate::access_control::*;
+#[cfg(feature = "audit-events")]
+use crate::audit::*;
+use crate::error::*;
 use crate::global_flags::*;
+#[cfg(feature = "multisig")]
+use crate::multisig::*;
 #[cfg(test)]
 use crate::testing::*;
 use crate::types::*;
@@ -21,9 +28,13 @@ use rustic_macros::modifiers;
 /// Guard method for validating when a canister is not paused.
 /// This is typically used in conjunction with the [`modifiers`] macro.
 pub fn when_not_paused() -> Result<(), String> {
-    #[allow(clippy::unwrap_used)] // unwrap desired
-    if GLOBAL_FLAGS.with(|f| f.borrow().get().0.clone().unwrap().paused) {
-        Err("Contract is paused".to_string())
+    Ok(check_not_paused()?)
+}
+
+/// Checks that the canister is not paused, returning a typed error.
+pub fn check_not_paused() -> Result<(), RusticError> {
+    if is_paused() {
+        Err(RusticError::Paused)
     } else {
         Ok(())
     }
@@ -32,11 +43,27 @@ pub fn when_not_paused() -> Result<(), String> {
 /// Guard method for validating when a canister is paused.
 /// This is typically used in conjunction with the [`modifiers`] macro.
 pub fn when_paused() -> Result<(), String> {
-    #[allow(clippy::unwrap_used)] // unwrap desired
-    if GLOBAL_FLAGS.with(|f| f.borrow().get().0.clone().unwrap().paused) {
+    Ok(check_paused()?)
+}
+
+/// Checks that the canister is paused, returning a typed error.
+pub fn check_paused() -> Result<(), RusticError> {
+    if is_paused() {
         Ok(())
     } else {
-        Err("Contract is not paused".to_string())
+        Err(RusticError::NotPaused)
+    }
+}
+
+impl Guard {
+    /// Passes if the canister is not paused, see [`when_not_paused`].
+    pub fn not_paused() -> Self {
+        Self::new("not paused", check_not_paused)
+    }
+
+    /// Passes if the canister is paused, see [`when_paused`].
+    pub fn paused() -> Self {
+        Self::new("paused", check_paused)
     }
 }
 
@@ -50,29 +77,57 @@ pub fn is_paused() -> bool {
 /// Pauses the canister. Can only be called by admins.
 #[update]
 #[modifiers("only_admin")]
+#[cfg_attr(feature = "multisig", modifiers("when_multisig_disabled"))]
 pub fn pause() {
-    GLOBAL_FLAGS.with(|f| {
-        let mut f = f.borrow_mut();
-        #[allow(clippy::unwrap_used)] // unwrap desired
-        let mut flags = f.get().0.clone().unwrap();
-        flags.paused = true;
-        #[allow(clippy::expect_used)] // unwrap desired
-        f.set(Cbor(Some(flags))).expect("Pause failed");
-    });
+    unwrap_or_reject(try_pause())
+}
+
+/// Same as [`pause`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_pause() -> Result<(), RusticError> {
+    check_admin()?;
+    #[cfg(feature = "multisig")]
+    check_multisig_disabled()?;
+    set_paused(true);
+    Ok(())
 }
 
 /// Resumes the canister. Can only be called by admins.
 #[update]
 #[modifiers("only_admin")]
+#[cfg_attr(feature = "multisig", modifiers("when_multisig_disabled"))]
 pub fn resume() {
+    unwrap_or_reject(try_resume())
+}
+
+/// Same as [`resume`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_resume() -> Result<(), RusticError> {
+    check_admin()?;
+    #[cfg(feature = "multisig")]
+    check_multisig_disabled()?;
+    set_paused(false);
+    Ok(())
+}
+
+pub(crate) fn set_paused(paused: bool) {
     GLOBAL_FLAGS.with(|f| {
         let mut f = f.borrow_mut();
         #[allow(clippy::unwrap_used)] // unwrap desired
         let mut flags = f.get().0.clone().unwrap();
-        flags.paused = false;
+        flags.paused = paused;
         #[allow(clippy::expect_used)] // unwrap desired
-        f.set(Cbor(Some(flags))).expect("Resume failed");
+        f.set(Cbor(Some(flags))).expect("Pause failed");
     });
+    #[cfg(feature = "audit-events")]
+    emit(
+        if paused {
+            AuditAction::Paused
+        } else {
+            AuditAction::Resumed
+        },
+        None,
+    );
 }
 
 // TODO: add pause/resume from roles
@@ -80,16 +135,42 @@ pub fn resume() {
 #[cfg(test)]
 mod unit_tests {
     use super::*;
+    use crate::utils::canister_caller;
     use candid::Principal;
 
     #[test]
     fn test_pause() {
         set_mock_caller(Principal::from_text("a4gq6-oaaaa-aaaab-qaa4q-cai").unwrap());
         global_flags_init();
+        access_init(canister_caller());
         assert!(!is_paused());
         pause();
         assert!(is_paused());
         resume();
         assert!(!is_paused());
     }
+
+    #[test]
+    fn test_pause_guards() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        global_flags_init();
+        access_init(canister_caller());
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        let g = all_of([
+            Guard::not_paused(),
+            any_of([Guard::admin(), Guard::owner()]),
+        ]);
+        assert_eq!(
+            g.check(),
+            Err(RusticError::NoneOf(vec![
+                RusticError::NotAdmin,
+                RusticError::NotOwner
+            ]))
+        );
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        assert!(g.check().is_ok());
+        pause();
+        assert_eq!(g.check(), Err(RusticError::Paused));
+        assert!(guard(all_of([Guard::paused(), Guard::owner()])).is_ok());
+    }
 }
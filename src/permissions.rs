@@ -0,0 +1,281 @@
+#![cfg(feature = "permissions")]
+
+//! Per-method permissions configurable at runtime, similar to OpenZeppelin AccessManager.
+//!
+//! The permission table maps method names to a [`Permission`], an expression over the owner, admins and roles.
+//! Admins edit the table with [`set_permission`] and [`remove_permission`], so who may call a method
+//! can be changed without an upgrade.
+//!
+//! Methods are protected with the [`authorized`] guard, which takes the name of the method to look up.
+//! The IC only exposes the method name of a call to `canister_inspect_message`, so the name has to be passed explicitly.
+//! Pass [`rustic::function!()`](crate::function), which expands to the path of the guarded method,
+//! so that the name cannot get out of sync with the method. The guard looks up the last segment of the path.
+//! Methods without an entry in the table can only be called by admins,
+//! so a misspelled name locks the method to admins rather than opening it up.
+//!
+//! # Examples
+//! ```rust
+//! # use ic_cdk::update;
+//! # use rustic::permissions::authorized;
+//! # use rustic_macros::modifiers;
+//! #[update]
+//! #[modifiers("authorized@rustic::function!()")]
+//! fn mint() {}
+//! ```
+//!
+//! The table is kept in a stable map, so it survives upgrades.
+//! With the `audit-events` feature, changes of the table are recorded in the audit log.
+
+use crate::access_control::*;
+#[cfg(feature = "audit-events")]
+use crate::audit::*;
+use crate::error::*;
+use crate::memory_map::*;
+#[cfg(test)]
+use crate::testing::*;
+use crate::types::*;
+use crate::utils::*;
+use candid::{CandidType, Principal};
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use rustic_macros::modifiers;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+/// Maximum number of permissions returned in a single page.
+pub const MAX_PERMISSIONS_PAGE_SIZE: u64 = 100;
+
+/// Who may call a method.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum Permission {
+    /// Any caller, including the anonymous principal.
+    Anyone,
+    Owner,
+    Admin,
+    /// Callers with the role, including delegated roles.
+    Role(u8),
+    /// Callers satisfying all of the permissions.
+    AllOf(Vec<Permission>),
+    /// Callers satisfying any of the permissions.
+    AnyOf(Vec<Permission>),
+}
+
+impl Permission {
+    /// Checks whether a principal satisfies the permission.
+    pub fn allows(&self, principal: Principal) -> bool {
+        match self {
+            Permission::Anyone => true,
+            Permission::Owner => is_owner(principal),
+            Permission::Admin => is_admin(principal),
+            Permission::Role(role) => user_has_role(*role, principal),
+            Permission::AllOf(permissions) => permissions.iter().all(|p| p.allows(principal)),
+            Permission::AnyOf(permissions) => permissions.iter().any(|p| p.allows(principal)),
+        }
+    }
+}
+
+thread_local! {
+    // can be lazily initialized
+    // mapping from method name to its permission
+    static PERMISSIONS: RefCell<StableBTreeMap<String, Cbor<Permission>, VM>> =
+        MEMORY_MANAGER.with(|mm| {
+            RefCell::new(StableBTreeMap::init(
+                mm.borrow().get(PERMISSIONS_MEM_ID)))
+    });
+}
+
+/// Returns the permission of a method, if set.
+#[query]
+pub fn get_permission(method: String) -> Option<Permission> {
+    PERMISSIONS.with(|p| p.borrow().get(&method).map(|x| x.0))
+}
+
+/// A page of the permission table.
+/// `next` is the method name to pass as `start` for the following page, or `None` if there are no more entries.
+#[derive(Clone, CandidType, Serialize, Deserialize)]
+pub struct PermissionsPage {
+    pub entries: Vec<(String, Permission)>,
+    pub next: Option<String>,
+}
+
+/// Returns a page of at most `limit` entries of the permission table, in ascending order of method name starting from `start`.
+#[query]
+pub fn get_permissions(start: Option<String>, limit: u64) -> PermissionsPage {
+    let limit = limit.min(MAX_PERMISSIONS_PAGE_SIZE) as usize;
+    PERMISSIONS.with(|p| {
+        let mut entries: Vec<(String, Permission)> = p
+            .borrow()
+            .range(start.unwrap_or_default()..)
+            .take(limit + 1)
+            .map(|(k, v)| (k, v.0))
+            .collect();
+        let next = (entries.len() > limit)
+            .then(|| entries.pop())
+            .flatten()
+            .map(|(k, _)| k);
+        PermissionsPage { entries, next }
+    })
+}
+
+/// Checks whether a principal may call a method.
+/// Methods without a permission can only be called by admins.
+#[query]
+pub fn user_is_authorized(method: String, principal: Principal) -> bool {
+    get_permission(method).map_or_else(|| is_admin(principal), |p| p.allows(principal))
+}
+
+// Returns the method name of a path given by `function!()`, i.e. its last segment.
+// The body of an async method is a closure, whose path ends with `{{closure}}` segments.
+fn method_name(path: &str) -> &str {
+    let mut path = path;
+    while let Some(parent) = path.strip_suffix("::{{closure}}") {
+        path = parent;
+    }
+    path.rsplit("::").next().unwrap_or(path)
+}
+
+/// Checks whether the caller may call a method according to the permission table.
+/// This is typically used in conjunction with the [`modifiers`] macro, passing
+/// [`rustic::function!()`](crate::function) as the method, see the [module documentation](self).
+pub fn authorized(method: &str) -> Result<(), String> {
+    Ok(check_authorized(method)?)
+}
+
+/// Checks whether the caller may call a method according to the permission table, returning a typed error.
+/// The method may be given as a path, of which only the last segment is looked up.
+pub fn check_authorized(method: &str) -> Result<(), RusticError> {
+    let method = method_name(method);
+    if user_is_authorized(method.to_string(), canister_caller()) {
+        Ok(())
+    } else {
+        Err(RusticError::Unauthorized(method.to_string()))
+    }
+}
+
+/// Sets the permission of a method, replacing any previous permission. Must be called by admins.
+#[update]
+#[modifiers("only_admin")]
+pub fn set_permission(method: String, permission: Permission) {
+    unwrap_or_reject(try_set_permission(method, permission))
+}
+
+/// Same as [`set_permission`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_set_permission(method: String, permission: Permission) -> Result<(), RusticError> {
+    check_admin()?;
+    PERMISSIONS.with(|p| p.borrow_mut().insert(method.clone(), Cbor(permission)));
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::PermissionSet(method), None);
+    Ok(())
+}
+
+/// Removes the permission of a method, so that it can only be called by admins. Must be called by admins.
+#[update]
+#[modifiers("only_admin")]
+pub fn remove_permission(method: String) {
+    unwrap_or_reject(try_remove_permission(method))
+}
+
+/// Same as [`remove_permission`], but returns an error instead of rejecting the call.
+#[update]
+pub fn try_remove_permission(method: String) -> Result<(), RusticError> {
+    check_admin()?;
+    PERMISSIONS.with(|p| p.borrow_mut().remove(&method));
+    #[cfg(feature = "audit-events")]
+    emit(AuditAction::PermissionRemoved(method), None);
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn test_permissions() {
+        let users =
+            [MOCK_USER_0, MOCK_USER_1, MOCK_USER_2].map(|u| Principal::from_text(u).unwrap());
+        set_mock_caller(users[0]);
+        access_init(canister_caller());
+        set_permission("mint".to_string(), Permission::Role(1));
+        set_permission(
+            "burn".to_string(),
+            Permission::AnyOf(vec![
+                Permission::Owner,
+                Permission::AllOf(vec![Permission::Role(1), Permission::Role(2)]),
+            ]),
+        );
+        set_permission("ping".to_string(), Permission::Anyone);
+        grant_roles(vec![(1, None)], users[1]);
+        grant_roles(vec![(1, None), (2, None)], users[2]);
+
+        assert!(authorized("burn").is_ok());
+        assert_eq!(
+            check_authorized("mint"),
+            Err(RusticError::Unauthorized("mint".to_string()))
+        );
+        set_mock_caller(users[1]);
+        assert!(authorized("mint").is_ok());
+        assert!(authorized("burn").is_err());
+        assert!(authorized("ping").is_ok());
+        // methods without permission are restricted to admins
+        assert!(authorized("withdraw").is_err());
+        assert!(user_is_authorized("withdraw".to_string(), users[0]));
+        set_mock_caller(users[2]);
+        assert!(authorized("burn").is_ok());
+
+        let page = get_permissions(None, 2);
+        assert_eq!(page.entries.len(), 2);
+        assert_eq!(page.entries[0].0, "burn");
+        assert_eq!(page.next, Some("ping".to_string()));
+        let page = get_permissions(page.next, 10);
+        assert_eq!(page.entries.len(), 1);
+        assert_eq!(page.entries[0].0, "ping");
+        assert_eq!(page.next, None);
+
+        assert_eq!(
+            try_remove_permission("mint".to_string()),
+            Err(RusticError::NotAdmin)
+        );
+        set_mock_caller(users[0]);
+        remove_permission("mint".to_string());
+        assert_eq!(get_permission("mint".to_string()), None);
+        assert!(!user_is_authorized("mint".to_string(), users[1]));
+    }
+
+    #[modifiers("authorized@crate::function!()")]
+    fn ping() {}
+
+    #[test]
+    fn test_method_name() {
+        assert_eq!(method_name("mint"), "mint");
+        assert_eq!(method_name("canister::token::mint"), "mint");
+        assert_eq!(method_name("canister::mint::{{closure}}"), "mint");
+    }
+
+    #[test]
+    fn test_authorized_function_name() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_permission("ping".to_string(), Permission::Anyone);
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        ping();
+    }
+
+    #[test]
+    #[should_panic(expected = "msg_reject should only be called inside canisters")]
+    fn test_authorized_function_name_unauth() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        ping();
+    }
+
+    #[test]
+    #[should_panic(expected = "msg_reject should only be called inside canisters")]
+    fn test_set_permission_unauth() {
+        set_mock_caller(Principal::from_text(MOCK_USER_0).unwrap());
+        access_init(canister_caller());
+        set_mock_caller(Principal::from_text(MOCK_USER_1).unwrap());
+        set_permission("mint".to_string(), Permission::Anyone);
+    }
+}
@@ -25,6 +25,7 @@
 //! # Attention
 //! The variable name must be `_guard` or `_some_text` and not `_` in order for the drop checker to be properly scoped.
 
+use crate::error::*;
 use crate::memory_map::*;
 use crate::types::*;
 use crate::utils::*;
@@ -47,14 +48,20 @@ thread_local! {
 
 impl ReentrancyGuard {
     pub fn new() -> Self {
+        match Self::try_new() {
+            Ok(guard) => guard,
+            Err(e) => ic_cdk::trap(&e.to_string()),
+        }
+    }
+
+    /// Same as [`ReentrancyGuard::new`], but returns [`RusticError::Reentrant`] instead of trapping.
+    pub fn try_new() -> Result<Self, RusticError> {
         let caller = canister_caller();
         if REENTRANCY_GUARD_MAP.with(|g| g.borrow().contains_key(&caller.into())) {
-            ic_cdk::trap("ReentrancyGuard: reentrant call");
-            //panic!("ReentrancyGuard: reentrant call");
-            //unreachable!();
+            return Err(RusticError::Reentrant);
         }
         REENTRANCY_GUARD_MAP.with(|g| g.borrow_mut().insert(caller.into(), ()));
-        Self { caller }
+        Ok(Self { caller })
     }
 }
 
@@ -94,6 +101,18 @@ mod unit_tests {
         test_reentrancy_guard_non_reentrant();
     }
 
+    #[test]
+    fn test_reentrancy_guard_try_new() {
+        let guard = ReentrancyGuard::try_new();
+        assert!(guard.is_ok());
+        assert_eq!(
+            ReentrancyGuard::try_new().err(),
+            Some(RusticError::Reentrant)
+        );
+        drop(guard);
+        assert!(ReentrancyGuard::try_new().is_ok());
+    }
+
     // #[test]
     // #[should_panic(expected = "trap should only be called inside canisters")]
     // #[rustic_macros::modifiers("non_reentrant")]
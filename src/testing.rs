@@ -12,9 +12,14 @@ struct MockData {
     id: Principal,
     version: u64,
     canister_balance: u128,
+    stable_pages: u64,
+    heap_size: u64,
     time: Option<u64>,
     instruction_counter: Option<u64>,
     controllers: Vec<Principal>,
+    recovering_from_trap: bool,
+    certified_data: Vec<u8>,
+    data_certificate: Option<Vec<u8>>,
 }
 
 impl MockData {
@@ -24,9 +29,14 @@ impl MockData {
             id: Principal::from_text(MOCK_ID).unwrap(),
             version: 0,
             canister_balance: 1000000000000,
+            stable_pages: 0,
+            heap_size: 0,
             time: None,
             instruction_counter: Some(1000000),
             controllers: vec![],
+            recovering_from_trap: false,
+            certified_data: vec![],
+            data_certificate: None,
         }
     }
 }
@@ -61,6 +71,20 @@ pub fn set_mock_balance(balance: u128) {
     });
 }
 
+/// Sets the mock stable memory size in pages for unit testing.
+pub fn set_mock_stable_pages(pages: u64) {
+    MOCK_DATA.with(|data| {
+        data.borrow_mut().stable_pages = pages;
+    });
+}
+
+/// Sets the mock heap memory size in bytes for unit testing.
+pub fn set_mock_heap_size(size: u64) {
+    MOCK_DATA.with(|data| {
+        data.borrow_mut().heap_size = size;
+    });
+}
+
 /// Sets the mock time for unit testing.
 pub fn set_mock_time(time: u64) {
     MOCK_DATA.with(|data| {
@@ -89,6 +113,27 @@ pub fn remove_mock_controller(controller: Principal) {
     });
 }
 
+/// Sets whether the mock canister is recovering from a trap for unit testing.
+pub fn set_mock_recovering_from_trap(recovering: bool) {
+    MOCK_DATA.with(|data| {
+        data.borrow_mut().recovering_from_trap = recovering;
+    });
+}
+
+/// Sets the mock certified data for unit testing.
+pub fn set_mock_certified_data(certified_data: &[u8]) {
+    MOCK_DATA.with(|data| {
+        data.borrow_mut().certified_data = certified_data.to_vec();
+    });
+}
+
+/// Sets the mock data certificate returned in queries for unit testing.
+pub fn set_mock_data_certificate(certificate: Option<Vec<u8>>) {
+    MOCK_DATA.with(|data| {
+        data.borrow_mut().data_certificate = certificate;
+    });
+}
+
 /// Gets the mock controller for unit testing.
 pub fn mock_caller() -> Principal {
     MOCK_DATA.with(|data| data.borrow().caller)
@@ -109,6 +154,16 @@ pub fn mock_balance() -> u128 {
     MOCK_DATA.with(|data| data.borrow().canister_balance)
 }
 
+/// Gets the mock stable memory size in pages for unit testing.
+pub fn mock_stable_pages() -> u64 {
+    MOCK_DATA.with(|data| data.borrow().stable_pages)
+}
+
+/// Gets the mock heap memory size in bytes for unit testing.
+pub fn mock_heap_size() -> u64 {
+    MOCK_DATA.with(|data| data.borrow().heap_size)
+}
+
 /// Gets the mock time for unit testing.
 pub fn mock_time() -> u64 {
     MOCK_DATA.with(|data| {
@@ -134,6 +189,21 @@ pub fn mock_instruction_counter() -> u64 {
     })
 }
 
+/// Gets whether the mock canister is recovering from a trap for unit testing.
+pub fn mock_recovering_from_trap() -> bool {
+    MOCK_DATA.with(|data| data.borrow().recovering_from_trap)
+}
+
+/// Gets the mock certified data for unit testing.
+pub fn mock_certified_data() -> Vec<u8> {
+    MOCK_DATA.with(|data| data.borrow().certified_data.clone())
+}
+
+/// Gets the mock data certificate for unit testing.
+pub fn mock_data_certificate() -> Option<Vec<u8>> {
+    MOCK_DATA.with(|data| data.borrow().data_certificate.clone())
+}
+
 /// Checks if the given principal is a mock controller for unit testing.
 pub fn is_mock_controller(controller: &Principal) -> bool {
     MOCK_DATA.with(|data| data.borrow().controllers.contains(controller))
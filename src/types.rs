@@ -93,3 +93,83 @@ impl Storable for StablePrincipal {
             is_fixed_size: false,
         };
 }
+
+/// A set of roles in [0,256), stored as a bitflag of 256 bits.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct RoleSet([u64; 4]);
+
+impl RoleSet {
+    pub fn contains(&self, role: u8) -> bool {
+        self.0[role as usize / 64] & (1 << (role % 64)) != 0
+    }
+
+    pub fn insert(&mut self, role: u8) {
+        self.0[role as usize / 64] |= 1 << (role % 64);
+    }
+
+    pub fn remove(&mut self, role: u8) {
+        self.0[role as usize / 64] &= !(1 << (role % 64));
+    }
+
+    pub fn union(&self, other: &RoleSet) -> RoleSet {
+        RoleSet([0, 1, 2, 3].map(|i| self.0[i] | other.0[i]))
+    }
+
+    pub fn difference(&self, other: &RoleSet) -> RoleSet {
+        RoleSet([0, 1, 2, 3].map(|i| self.0[i] & !other.0[i]))
+    }
+
+    pub fn intersection(&self, other: &RoleSet) -> RoleSet {
+        RoleSet([0, 1, 2, 3].map(|i| self.0[i] & other.0[i]))
+    }
+
+    pub fn intersects(&self, other: &RoleSet) -> bool {
+        self.0.iter().zip(other.0.iter()).any(|(a, b)| a & b != 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|x| *x == 0)
+    }
+
+    /// Returns the roles in the set in ascending order.
+    pub fn roles(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..=u8::MAX).filter(|role| self.contains(*role))
+    }
+}
+
+impl From<u32> for RoleSet {
+    fn from(flags: u32) -> Self {
+        Self([flags as u64, 0, 0, 0])
+    }
+}
+
+impl FromIterator<u8> for RoleSet {
+    fn from_iter<I: IntoIterator<Item = u8>>(roles: I) -> Self {
+        let mut set = RoleSet::default();
+        roles.into_iter().for_each(|role| set.insert(role));
+        set
+    }
+}
+
+impl Storable for RoleSet {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(self.0.iter().flat_map(|x| x.to_le_bytes()).collect())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let mut words = [0; 4];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(8)) {
+            #[allow(clippy::unwrap_used)] // unwrap expected
+            {
+                *word = u64::from_le_bytes(chunk.try_into().unwrap());
+            }
+        }
+        Self(words)
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Bounded {
+            max_size: 32,
+            is_fixed_size: true,
+        };
+}
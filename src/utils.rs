@@ -1,15 +1,12 @@
 //! Utility functions.
+//!
+//! The caller classification guards, such as [`not_anonymous`] and [`only_controller`],
+//! are typically used in conjunction with the [`modifiers`](rustic_macros::modifiers) macro.
+//! They use the mockable [`canister_caller`] and [`is_controller`], so they can be unit tested with [`crate::testing`].
 
-use candid::Principal;
-
-// Anonymous callers can be dangerous if not properly validated
-// example: `#[rustic_macros::modifiers("not_anonymous@caller")]`
-#[allow(dead_code)]
-fn not_anonymous(principal: Principal) -> Result<(), String> {
-    (principal != candid::Principal::anonymous())
-        .then_some(())
-        .ok_or("Anonymous caller is not allowed".to_string())
-}
+use crate::error::RusticError;
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
 
 // Macro for displaying `type_name_of`
 #[macro_export]
@@ -79,6 +76,28 @@ pub fn canister_balance128() -> u128 {
     return super::testing::mock_balance();
 }
 
+#[inline]
+pub fn canister_stable_pages() -> u64 {
+    #[cfg(not(test))]
+    return ic_cdk::api::stable::stable64_size();
+
+    #[cfg(test)]
+    return super::testing::mock_stable_pages();
+}
+
+/// Returns the size of the heap memory in bytes.
+#[inline]
+pub fn canister_heap_size() -> u64 {
+    #[cfg(all(not(test), target_arch = "wasm32"))]
+    return core::arch::wasm32::memory_size(0) as u64 * 65536;
+
+    #[cfg(all(not(test), not(target_arch = "wasm32")))]
+    return 0;
+
+    #[cfg(test)]
+    return super::testing::mock_heap_size();
+}
+
 #[inline]
 pub fn instruction_counter() -> u64 {
     #[cfg(not(test))]
@@ -107,6 +126,34 @@ pub fn is_controller(caller: &Principal) -> bool {
     return super::testing::is_mock_controller(caller);
 }
 
+#[inline]
+pub fn is_recovering_from_trap() -> bool {
+    #[cfg(not(test))]
+    return ic_cdk::api::call::is_recovering_from_trap();
+
+    #[cfg(test)]
+    return super::testing::mock_recovering_from_trap();
+}
+
+#[inline]
+pub fn canister_set_certified_data(data: &[u8]) {
+    #[cfg(not(test))]
+    ic_cdk::api::set_certified_data(data);
+
+    #[cfg(test)]
+    super::testing::set_mock_certified_data(data);
+}
+
+/// Returns the certificate of the certified data. Only available in query calls.
+#[inline]
+pub fn canister_data_certificate() -> Option<Vec<u8>> {
+    #[cfg(not(test))]
+    return ic_cdk::api::data_certificate();
+
+    #[cfg(test)]
+    return super::testing::mock_data_certificate();
+}
+
 #[inline]
 pub fn canister_print<S: std::convert::AsRef<str>>(s: S) {
     #[cfg(not(test))]
@@ -124,3 +171,136 @@ pub fn canister_trap(message: &str) {
     #[cfg(test)]
     panic!("{}", message);
 }
+
+// Class tags of principals, the last byte of the principal.
+const OPAQUE_ID_TAG: u8 = 0x01;
+const SELF_AUTHENTICATING_TAG: u8 = 0x02;
+
+/// Kind of a principal, derived from its class tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum PrincipalKind {
+    /// The anonymous principal.
+    Anonymous,
+    /// A self-authenticating principal, i.e. a user authenticated with a key pair.
+    User,
+    /// A canister, or another principal with an opaque id.
+    Canister,
+    /// The management canister, derived and reserved principals.
+    Other,
+}
+
+/// Classifies a principal.
+pub fn principal_kind(principal: &Principal) -> PrincipalKind {
+    if *principal == Principal::anonymous() {
+        return PrincipalKind::Anonymous;
+    }
+    match principal.as_slice().last() {
+        Some(&SELF_AUTHENTICATING_TAG) => PrincipalKind::User,
+        Some(&OPAQUE_ID_TAG) => PrincipalKind::Canister,
+        _ => PrincipalKind::Other,
+    }
+}
+
+/// Classifies the caller.
+pub fn caller_kind() -> PrincipalKind {
+    principal_kind(&canister_caller())
+}
+
+/// Checks that the caller is not anonymous, returning a typed error.
+pub fn check_not_anonymous() -> Result<(), RusticError> {
+    if caller_kind() == PrincipalKind::Anonymous {
+        Err(RusticError::AnonymousCaller)
+    } else {
+        Ok(())
+    }
+}
+
+/// Guard method for rejecting anonymous callers.
+/// Anonymous callers can be dangerous if not properly validated.
+pub fn not_anonymous() -> Result<(), String> {
+    Ok(check_not_anonymous()?)
+}
+
+/// Checks that the caller is a user with a self-authenticating principal, returning a typed error.
+pub fn check_user_caller() -> Result<(), RusticError> {
+    if caller_kind() == PrincipalKind::User {
+        Ok(())
+    } else {
+        Err(RusticError::NotUserCaller)
+    }
+}
+
+/// Guard method for callers that are users with a self-authenticating principal.
+pub fn only_user_caller() -> Result<(), String> {
+    Ok(check_user_caller()?)
+}
+
+/// Checks that the caller is a canister, returning a typed error.
+pub fn check_canister_caller() -> Result<(), RusticError> {
+    if caller_kind() == PrincipalKind::Canister {
+        Ok(())
+    } else {
+        Err(RusticError::NotCanisterCaller)
+    }
+}
+
+/// Guard method for callers that are canisters.
+pub fn only_canister_caller() -> Result<(), String> {
+    Ok(check_canister_caller()?)
+}
+
+/// Checks that the caller is a controller of the canister, returning a typed error.
+pub fn check_controller() -> Result<(), RusticError> {
+    if is_controller(&canister_caller()) {
+        Ok(())
+    } else {
+        Err(RusticError::NotController)
+    }
+}
+
+/// Guard method for callers that are controllers of the canister.
+/// Note that controllers are system level admins, see [`crate::access_control`] for application level access control.
+pub fn only_controller() -> Result<(), String> {
+    Ok(check_controller()?)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn test_caller_classification() {
+        let user = Principal::from_text(MOCK_USER_0).unwrap();
+        let canister = Principal::from_text(MOCK_CANISTER_0).unwrap();
+        assert_eq!(principal_kind(&user), PrincipalKind::User);
+        assert_eq!(principal_kind(&canister), PrincipalKind::Canister);
+        assert_eq!(
+            principal_kind(&Principal::management_canister()),
+            PrincipalKind::Other
+        );
+
+        set_mock_caller(Principal::anonymous());
+        assert_eq!(caller_kind(), PrincipalKind::Anonymous);
+        assert_eq!(check_not_anonymous(), Err(RusticError::AnonymousCaller));
+        assert!(only_user_caller().is_err());
+
+        set_mock_caller(user);
+        assert!(not_anonymous().is_ok());
+        assert!(only_user_caller().is_ok());
+        assert_eq!(check_canister_caller(), Err(RusticError::NotCanisterCaller));
+        assert_eq!(check_controller(), Err(RusticError::NotController));
+        add_mock_controller(user);
+        assert!(only_controller().is_ok());
+
+        set_mock_caller(canister);
+        assert!(only_canister_caller().is_ok());
+        assert_eq!(check_user_caller(), Err(RusticError::NotUserCaller));
+        assert!(only_controller().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "msg_reject should only be called inside canisters")]
+    #[rustic_macros::modifiers("only_canister_caller")]
+    fn test_syntax_caller_modifiers() {}
+}